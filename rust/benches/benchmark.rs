@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
+#[allow(clippy::redundant_closure_call)]
 fn closure_bench(c: &mut Criterion) {
     let iterations = 100_000;
     c.bench_function("closure", |b| {
@@ -1,5 +1,7 @@
 //! Abstract Syntax Tree definitions for Lua code.
 
+use std::fmt;
+
 /// Root AST node representing a Lua chunk.
 #[derive(Debug, Clone, PartialEq)]
 pub struct AstNode {
@@ -25,35 +27,246 @@ impl Block {
     }
 }
 
-/// Statements supported by the simplified parser.
+/// Location of a node in the source code.
+///
+/// `start` and `end` are byte offsets, `line` and `column` give the position
+/// of `start`. Nodes created by steps use [`Span::default`], which points at
+/// nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self { start, end, line, column }
+    }
+
+    /// Span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+
+    /// Whether the span was produced by the parser rather than a step.
+    pub fn is_source(&self) -> bool {
+        *self != Span::default()
+    }
+}
+
+/// A statement and where it was found.
+///
+/// Spans do not take part in comparisons, so trees built by steps compare
+/// equal to parsed ones.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Self {
+        Statement::new(kind, Span::default())
+    }
+}
+
+/// Lua statements.
 #[derive(Debug, Clone, PartialEq)]
-pub enum Statement {
-    /// `local name = expr`
-    LocalAssignment { name: String, expr: Expression },
-    /// `name = expr`
-    Assignment { name: String, expr: Expression },
-    /// `return expr?`
-    Return(Option<Expression>),
+pub enum StatementKind {
+    /// `local a, b = e1, e2`
+    LocalAssignment { names: Vec<String>, exprs: Vec<Expression> },
+    /// `local function name(...) ... end`
+    LocalFunction { name: String, body: FunctionBody },
+    /// `function a.b.c:method(...) ... end`
+    ///
+    /// `target` is either a variable or a chain of string-keyed indexes.
+    FunctionDeclaration {
+        target: Expression,
+        method: Option<String>,
+        body: FunctionBody,
+    },
+    /// `a, b.c, d[e] = e1, e2, e3`
+    Assignment { targets: Vec<Expression>, exprs: Vec<Expression> },
+    /// `do ... end`
+    Do(Block),
+    /// `while condition do ... end`
+    While { condition: Expression, block: Block },
+    /// `repeat ... until condition`
+    Repeat { block: Block, condition: Expression },
+    /// `if c1 then ... elseif c2 then ... else ... end`
+    If {
+        clauses: Vec<(Expression, Block)>,
+        else_block: Option<Block>,
+    },
+    /// `for var = start, limit, step do ... end`
+    NumericFor {
+        var: String,
+        start: Expression,
+        limit: Expression,
+        step: Option<Expression>,
+        block: Block,
+    },
+    /// `for a, b in e1, e2 do ... end`
+    GenericFor {
+        names: Vec<String>,
+        exprs: Vec<Expression>,
+        block: Block,
+    },
+    /// `return e1, e2`
+    Return(Vec<Expression>),
     /// `break`
     Break,
     /// `continue` – LuaU only.
     Continue,
-    /// Bare expression as a statement.
+    /// Function or method call used as a statement.
     Expression(Expression),
 }
 
-/// Expressions supported by the simplified parser.
+/// Parameter list and body shared by all function definitions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionBody {
+    pub params: Vec<String>,
+    pub is_vararg: bool,
+    pub block: Block,
+}
+
+impl FunctionBody {
+    pub fn new(params: Vec<String>, is_vararg: bool, block: Block) -> Self {
+        Self { params, is_vararg, block }
+    }
+}
+
+/// An expression and where it was found. Like [`Statement`], equality
+/// ignores the span.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Self {
+        Expression::new(kind, Span::default())
+    }
+}
+
+/// Expressions supported by the parser.
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expression {
+pub enum ExpressionKind {
+    Nil,
+    Boolean(bool),
     Number(f64),
     String(String),
+    /// `...`
+    Vararg,
     Variable(String),
+    /// `function(params) ... end`
+    Function(FunctionBody),
+    /// `{ a, b = c, [d] = e }`
+    Table(Vec<TableField>),
     /// Binary operator expression, such as `a + b`.
     BinaryOp {
         left: Box<Expression>,
         op: String,
         right: Box<Expression>,
     },
+    /// Unary operator expression: `-a`, `not a` or `#a`.
+    UnaryOp {
+        op: String,
+        operand: Box<Expression>,
+    },
+    /// Parenthesized expression. Kept in the tree because `(f())` and `(...)`
+    /// truncate multiple results to a single value.
+    Paren(Box<Expression>),
+    /// `object[key]`, also used for `object.name`.
+    Index {
+        object: Box<Expression>,
+        key: Box<Expression>,
+    },
+    /// `func(args)`
+    Call {
+        func: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    /// `object:method(args)`
+    MethodCall {
+        object: Box<Expression>,
+        method: String,
+        args: Vec<Expression>,
+    },
+}
+
+/// Entry of a table constructor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableField {
+    /// Positional entry: `{ value }`
+    Value(Expression),
+    /// `{ name = value }`
+    Named { name: String, value: Expression },
+    /// `{ [key] = value }`
+    Keyed { key: Expression, value: Expression },
+}
+
+/// Binding power of unary operators.
+pub const UNARY_PRIORITY: u8 = 8;
+
+/// Left and right binding power of a binary operator, following the priority
+/// table of the reference Lua 5.1 parser. `..` and `^` are right associative.
+pub fn binary_priority(op: &str) -> Option<(u8, u8)> {
+    Some(match op {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "==" => (3, 3),
+        ".." => (5, 4),
+        "+" | "-" => (6, 6),
+        "*" | "/" | "%" => (7, 7),
+        "^" => (10, 9),
+        _ => return None,
+    })
+}
+
+impl Expression {
+    /// Whether the expression may be used on the left side of an assignment.
+    pub fn is_assignable(&self) -> bool {
+        matches!(self.kind, ExpressionKind::Variable(_) | ExpressionKind::Index { .. })
+    }
+
+    /// Whether the expression is a function or method call.
+    pub fn is_call(&self) -> bool {
+        matches!(self.kind, ExpressionKind::Call { .. } | ExpressionKind::MethodCall { .. })
+    }
+
+    /// Whether the expression can produce more than one value when it is the
+    /// last entry of an expression list.
+    pub fn is_multi_value(&self) -> bool {
+        self.is_call() || matches!(self.kind, ExpressionKind::Vararg)
+    }
 }
 
 /// Parsing produced an error.
@@ -70,6 +283,14 @@ impl ParseError {
     }
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parsing Error at Position {}:{}, {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 /// Parsing produced a warning that did not abort parsing.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParseWarning {
@@ -84,16 +305,31 @@ impl ParseWarning {
     }
 }
 
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parsing Warning at Position {}:{}, {}", self.line, self.column, self.message)
+    }
+}
+
 /// Result of a parse operation.
+///
+/// `errors` is only ever filled by [`crate::parser::parse_recovering`]; in
+/// that case `ast` holds every statement that could be parsed.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParseResult {
     pub ast: AstNode,
     pub warnings: Vec<ParseWarning>,
+    pub errors: Vec<ParseError>,
 }
 
 impl ParseResult {
     pub fn new(ast: AstNode, warnings: Vec<ParseWarning>) -> Self {
-        Self { ast, warnings }
+        Self { ast, warnings, errors: Vec::new() }
+    }
+
+    /// Whether parsing finished without errors.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
     }
 }
 
@@ -1,25 +1,38 @@
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use prometheus_rs::{
-    colors,
+    colors::{self, Color},
+    config::NAME_UPPER,
+    lexer::tokenize,
+    parser::parse_recovering,
     logger::{Logger, LogLevel},
-    obfuscate, Config, LuaVersion, load_preset,
+    Config, ConfigFormat, LuaVersion, Pipeline, load_preset, preset_names,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Prometheus obfuscator CLI")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input Lua source file
-    source: PathBuf,
+    #[arg(required_unless_present = "list_presets")]
+    source: Option<PathBuf>,
 
     /// Use a built-in preset
     #[arg(short, long)]
     preset: Option<String>,
 
-    /// Load configuration from external JSON file
+    /// List the built-in presets and exit
+    #[arg(long)]
+    list_presets: bool,
+
+    /// Load configuration from external JSON, TOML or Lua file
     #[arg(short, long)]
     config: Option<PathBuf>,
 
@@ -50,19 +63,97 @@ struct Cli {
     /// Save errors to a .error.txt file
     #[arg(long)]
     saveerrors: bool,
+
+    /// Report every parse error and warning instead of stopping at the first error
+    #[arg(long)]
+    allerrors: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Work with configuration files
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the JSON Schema of configuration files
+    Schema {
+        /// Write the schema to this file instead
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Convert a configuration file between JSON, TOML and Lua, selected by
+    /// the file extensions
+    Convert {
+        /// Configuration file to read
+        input: PathBuf,
+        /// Configuration file to write
+        output: PathBuf,
+    },
+}
+
+/// Format of the configuration file at `path`.
+fn config_format(path: &Path) -> Result<ConfigFormat, String> {
+    ConfigFormat::from_path(path).ok_or_else(|| {
+        format!("{}: expected a .json, .toml or .lua config file", path.display())
+    })
+}
+
+fn run_command(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Config(ConfigCommand::Schema { out }) => {
+            let schema = Pipeline::from_config(Config::default())?.config_schema();
+            let text = serde_json::to_string_pretty(&schema)?;
+            match out {
+                Some(path) => fs::write(path, text + "\n")?,
+                None => println!("{text}"),
+            }
+        }
+        Command::Config(ConfigCommand::Convert { input, output }) => {
+            let config = Config::parse(&fs::read_to_string(&input)?, config_format(&input)?)?;
+            fs::write(&output, config.to_text(config_format(&output)?)?)?;
+        }
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
     colors::set_enabled(!cli.nocolors);
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", colors::colorize(format!("{NAME_UPPER}: {err}"), &[Color::Red]));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     let logger = Logger::new(cli.loglevel);
 
+    if let Some(command) = cli.command {
+        return run_command(command);
+    }
+
+    if cli.list_presets {
+        for name in preset_names() {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+    let source_path = cli.source.expect("clap requires a source");
+
     // Load configuration
     let mut config: Config = if let Some(preset) = cli.preset.as_deref() {
-        load_preset(preset).ok_or_else(|| format!("Preset '{preset}' not found"))?
+        load_preset(preset).ok_or_else(|| {
+            format!("Preset '{preset}' not found, available: {}", preset_names().join(", "))
+        })?
     } else if let Some(path) = cli.config.as_ref() {
         let text = fs::read_to_string(path)?;
-        serde_json::from_str(&text)?
+        Config::parse(&text, config_format(path)?)?
     } else {
         load_preset("Minify").expect("Default preset available")
     };
@@ -79,18 +170,30 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Determine output file
     let out_path = cli.out.unwrap_or_else(|| {
-        let mut p = cli.source.clone();
+        let mut p = source_path.clone();
         p.set_extension("obfuscated.lua");
         p
     });
 
-    let source = fs::read_to_string(&cli.source)?;
+    let source = fs::read_to_string(&source_path)?;
+
+    if cli.allerrors {
+        let tokens = tokenize(&source, config.lua_version)?;
+        let result = parse_recovering(&tokens, config.lua_version);
+        for warning in &result.warnings {
+            logger.warn(warning.to_string());
+        }
+        for error in &result.errors {
+            eprintln!("{}", colors::colorize(error.to_string(), &[Color::Red]));
+        }
+        if !result.is_ok() {
+            let count = result.errors.len();
+            return Err(format!("{count} parse error(s) in {}", source_path.display()).into());
+        }
+    }
 
-    // Placeholder obfuscation until Rust port is complete
-    let out = match std::panic::catch_unwind(|| obfuscate(&source)) {
-        Ok(code) => code,
-        Err(_) => format!("-- obfuscation not yet implemented\n{source}"),
-    };
+    let mut pipeline = Pipeline::from_config(config)?;
+    let out = pipeline.apply(&source)?;
 
     fs::write(&out_path, out)?;
     logger.log(format!("Wrote output to {}", out_path.display()));
@@ -0,0 +1,194 @@
+//! Instruction set executed by the emitted virtual machine.
+//!
+//! Every instruction has an opcode and three integer operands `a`, `b` and
+//! `c`. Registers are numbered from `1`. Operands documented as `RK` name a
+//! register when they are positive and the constant `-x` when negative.
+
+/// Operation performed by an [`Instruction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpCode {
+    /// `R[a] = R[b]`
+    Move,
+    /// `R[a] = K[b]`
+    LoadK,
+    /// `R[a] .. R[b] = nil`
+    LoadNil,
+    /// `R[a] = b == 1`
+    LoadBool,
+    /// `R[a] = env[K[b]]`
+    GetGlobal,
+    /// `env[K[b]] = R[a]`
+    SetGlobal,
+    /// `R[a] = upvalue b`
+    GetUpval,
+    /// `upvalue b = R[a]`
+    SetUpval,
+    /// `R[a] = box containing R[b]`
+    NewBox,
+    /// `R[a] = contents of the box in R[b]`
+    GetBox,
+    /// `contents of the box in R[a] = R[b]`
+    SetBox,
+    /// `R[a] = R[b][RK(c)]`
+    GetTable,
+    /// `R[a][RK(b)] = RK(c)`
+    SetTable,
+    /// `R[a] = {}`
+    NewTable,
+    /// `R[a][c + i - 1] = R[a + i]` for `b - 1` values, or up to the top if `b == 0`.
+    SetList,
+    /// `R[a + 1] = R[b]; R[a] = R[b][RK(c)]`
+    SelfOp,
+    /// `R[a] = RK(b) + RK(c)`
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    /// `R[a] = RK(b) == RK(c)`
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    /// `R[a] = -R[b]`
+    Unm,
+    /// `R[a] = not R[b]`
+    Not,
+    /// `R[a] = #R[b]`
+    Len,
+    /// Continue at instruction `a`.
+    Jmp,
+    /// Continue at instruction `b` if `R[a]` is truthy.
+    JmpIf,
+    /// Continue at instruction `b` if `R[a]` is falsy.
+    JmpIfNot,
+    /// Call `R[a]` with `b - 1` arguments (up to the top if `b == 0`) and store
+    /// `c - 1` results from `R[a]` (all results, setting the top, if `c == 0`).
+    Call,
+    /// Call `R[a]` with `b - 1` arguments (up to the top if `b == 0`) and
+    /// return all of its results.
+    TailCall,
+    /// Return `b - 1` values starting at `R[a]`, or up to the top if `b == 0`.
+    Return,
+    /// Copy `b - 1` varargs to `R[a]`, or all of them if `b == 0`.
+    Vararg,
+    /// `R[a] = closure of child prototype b`
+    Closure,
+    /// Prepare the numeric loop in `R[a] .. R[a + 2]` and continue at `b`.
+    ForPrep,
+    /// Step the numeric loop in `R[a]`; while it runs copy the counter to
+    /// `R[a + 3]` and continue at `b`.
+    ForLoop,
+    /// Call the iterator in `R[a]` and store `c` results from `R[a + 3]`;
+    /// unless the first is nil, update the control variable and continue at `b`.
+    TForLoop,
+}
+
+impl OpCode {
+    pub const ALL: [OpCode; 41] = [
+        OpCode::Move,
+        OpCode::LoadK,
+        OpCode::LoadNil,
+        OpCode::LoadBool,
+        OpCode::GetGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpval,
+        OpCode::SetUpval,
+        OpCode::NewBox,
+        OpCode::GetBox,
+        OpCode::SetBox,
+        OpCode::GetTable,
+        OpCode::SetTable,
+        OpCode::NewTable,
+        OpCode::SetList,
+        OpCode::SelfOp,
+        OpCode::Add,
+        OpCode::Sub,
+        OpCode::Mul,
+        OpCode::Div,
+        OpCode::Mod,
+        OpCode::Pow,
+        OpCode::Concat,
+        OpCode::Eq,
+        OpCode::Ne,
+        OpCode::Lt,
+        OpCode::Le,
+        OpCode::Unm,
+        OpCode::Not,
+        OpCode::Len,
+        OpCode::Jmp,
+        OpCode::JmpIf,
+        OpCode::JmpIfNot,
+        OpCode::Call,
+        OpCode::TailCall,
+        OpCode::Return,
+        OpCode::Vararg,
+        OpCode::Closure,
+        OpCode::ForPrep,
+        OpCode::ForLoop,
+        OpCode::TForLoop,
+    ];
+
+    /// Opcode implementing a binary operator of the AST, if it maps to one
+    /// directly. `>` and `>=` are compiled as swapped `<` and `<=`.
+    pub fn from_binary_operator(op: &str) -> Option<OpCode> {
+        Some(match op {
+            "+" => OpCode::Add,
+            "-" => OpCode::Sub,
+            "*" => OpCode::Mul,
+            "/" => OpCode::Div,
+            "%" => OpCode::Mod,
+            "^" => OpCode::Pow,
+            ".." => OpCode::Concat,
+            "==" => OpCode::Eq,
+            "~=" => OpCode::Ne,
+            "<" => OpCode::Lt,
+            "<=" => OpCode::Le,
+            _ => return None,
+        })
+    }
+}
+
+/// A single VM instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub op: OpCode,
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
+}
+
+impl Instruction {
+    pub fn new(op: OpCode, a: i64, b: i64, c: i64) -> Self {
+        Self { op, a, b, c }
+    }
+}
+
+/// Constant referenced by `LoadK`, `RK` operands and global accesses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    String(String),
+}
+
+/// Where a closure finds one of its upvalues when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpvalueSource {
+    /// The box held in a register of the enclosing function.
+    Register(i64),
+    /// An upvalue of the enclosing function.
+    Upvalue(i64),
+}
+
+/// Compiled function.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Proto {
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Constant>,
+    pub protos: Vec<Proto>,
+    pub upvalues: Vec<UpvalueSource>,
+    pub num_params: usize,
+    pub is_vararg: bool,
+}
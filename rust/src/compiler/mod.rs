@@ -0,0 +1,839 @@
+//! Compiler from the AST to the register based instruction set in
+//! [`bytecode`]. Together with the interpreter emitted by [`vm`] it implements
+//! the Vmify step.
+//!
+//! Locals live in registers. Locals that are captured by a nested function are
+//! stored in a box (a one element table) instead, so that every closure sees
+//! the same variable; a fresh box is created each time the declaration runs.
+
+pub mod bytecode;
+pub mod vm;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, FunctionBody, Span, Statement, StatementKind,
+    TableField,
+};
+use crate::scope::{BindingId, ScopeInfo};
+
+use bytecode::{Constant, Instruction, OpCode, Proto, UpvalueSource};
+
+/// Number of table entries stored by a single `SetList`.
+const FIELDS_PER_FLUSH: i64 = 50;
+
+/// The AST could not be compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl CompileError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self { message: message.into(), span }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Compile Error at Position {}:{}, {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compile the main chunk of `ast` into a vararg [`Proto`].
+///
+/// `scope` must be the analysis of `ast`; it tells which locals are captured.
+pub fn compile(ast: &AstNode, scope: &ScopeInfo) -> Result<Proto, CompileError> {
+    let mut compiler = Compiler { scope, next_binding: 0, functions: Vec::new(), span: Span::default() };
+    compiler.function(&FunctionBody::new(Vec::new(), true, ast.block.clone()), false)
+}
+
+struct LocalVar {
+    name: String,
+    register: i64,
+    boxed: bool,
+}
+
+/// Jumps of a loop that are patched once its exit and continue targets are
+/// known.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(String),
+}
+
+/// State of the function being compiled.
+struct FunctionState {
+    proto: Proto,
+    constants: HashMap<ConstantKey, i64>,
+    locals: Vec<LocalVar>,
+    upvalue_names: Vec<String>,
+    /// Start of each open block: number of locals and first free register.
+    blocks: Vec<(usize, i64)>,
+    loops: Vec<Loop>,
+    /// First free register.
+    free: i64,
+}
+
+/// What a name refers to at the current position.
+enum Variable {
+    Local { register: i64, boxed: bool },
+    Upvalue(i64),
+    Global(String),
+}
+
+/// Destination of an assignment whose operands are already evaluated.
+enum Target {
+    Variable(Variable),
+    Index { object: i64, key: i64 },
+}
+
+struct Compiler<'a> {
+    scope: &'a ScopeInfo,
+    /// Bindings are declared in the same order as the scope analysis visits
+    /// them, so a counter identifies the binding of each declaration.
+    next_binding: usize,
+    functions: Vec<FunctionState>,
+    /// Latest statement to be compiled, reported by internal errors.
+    span: Span,
+}
+
+impl Compiler<'_> {
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn pc(&mut self) -> usize {
+        self.state().proto.code.len()
+    }
+
+    fn emit(&mut self, op: OpCode, a: i64, b: i64, c: i64) -> usize {
+        let code = &mut self.state().proto.code;
+        code.push(Instruction::new(op, a, b, c));
+        code.len() - 1
+    }
+
+    /// Point the jump emitted at `jump` to instruction `target`.
+    fn patch(&mut self, jump: usize, target: usize) {
+        let instruction = &mut self.state().proto.code[jump];
+        match instruction.op {
+            OpCode::Jmp => instruction.a = target as i64,
+            _ => instruction.b = target as i64,
+        }
+    }
+
+    fn patch_here(&mut self, jumps: impl IntoIterator<Item = usize>) {
+        let target = self.pc();
+        for jump in jumps {
+            self.patch(jump, target);
+        }
+    }
+
+    fn alloc(&mut self) -> i64 {
+        let state = self.state();
+        state.free += 1;
+        state.free - 1
+    }
+
+    fn free(&mut self) -> i64 {
+        self.state().free
+    }
+
+    fn set_free(&mut self, free: i64) {
+        self.state().free = free;
+    }
+
+    /// Index of a constant, usable as a negative `RK` operand.
+    fn constant(&mut self, constant: Constant) -> i64 {
+        let key = match &constant {
+            Constant::Number(n) => ConstantKey::Number(n.to_bits()),
+            Constant::String(s) => ConstantKey::String(s.clone()),
+        };
+        let state = self.state();
+        if let Some(&index) = state.constants.get(&key) {
+            return index;
+        }
+        state.proto.constants.push(constant);
+        let index = state.proto.constants.len() as i64;
+        state.constants.insert(key, index);
+        index
+    }
+
+    fn string_constant(&mut self, s: &str) -> i64 {
+        self.constant(Constant::String(s.to_string()))
+    }
+
+    fn enter_block(&mut self) {
+        let state = self.state();
+        let mark = (state.locals.len(), state.free);
+        state.blocks.push(mark);
+    }
+
+    fn exit_block(&mut self) {
+        let state = self.state();
+        let (locals, free) = state.blocks.pop().unwrap();
+        state.locals.truncate(locals);
+        state.free = free;
+    }
+
+    /// Declare `name` as living in `register`, boxing it in place if a nested
+    /// function captures it.
+    fn declare(&mut self, name: &str, register: i64) -> Result<bool, CompileError> {
+        let binding = self.scope.binding(BindingId(self.next_binding));
+        if binding.name != name {
+            return Err(self.internal_error(format!(
+                "compiler and scope analysis disagree, declared `{name}` but the scope has `{}`",
+                binding.name
+            )));
+        }
+        self.next_binding += 1;
+        let boxed = binding.captured;
+        if boxed {
+            self.emit(OpCode::NewBox, register, register, 0);
+        }
+        self.state().locals.push(LocalVar { name: name.to_string(), register, boxed });
+        Ok(boxed)
+    }
+
+    /// Error for a bug in the compiler rather than in the compiled code.
+    fn internal_error(&self, message: String) -> CompileError {
+        CompileError::new(format!("internal error: {message}"), self.span)
+    }
+
+    fn find_local(&self, level: usize, name: &str) -> Option<&LocalVar> {
+        self.functions[level].locals.iter().rev().find(|local| local.name == name)
+    }
+
+    /// Index of the upvalue through which function `level` reaches `name`.
+    fn find_upvalue(&mut self, level: usize, name: &str) -> Result<Option<i64>, CompileError> {
+        if level == 0 {
+            return Ok(None);
+        }
+        if let Some(index) = self.functions[level].upvalue_names.iter().position(|n| n == name) {
+            return Ok(Some(index as i64 + 1));
+        }
+        let source = match self.find_local(level - 1, name) {
+            Some(local) if local.boxed => UpvalueSource::Register(local.register),
+            Some(_) => return Err(self.internal_error(format!("captured local `{name}` is not boxed"))),
+            None => match self.find_upvalue(level - 1, name)? {
+                Some(index) => UpvalueSource::Upvalue(index),
+                None => return Ok(None),
+            },
+        };
+        let state = &mut self.functions[level];
+        state.upvalue_names.push(name.to_string());
+        state.proto.upvalues.push(source);
+        Ok(Some(state.upvalue_names.len() as i64))
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Variable, CompileError> {
+        let level = self.functions.len() - 1;
+        if let Some(local) = self.find_local(level, name) {
+            return Ok(Variable::Local { register: local.register, boxed: local.boxed });
+        }
+        Ok(match self.find_upvalue(level, name)? {
+            Some(index) => Variable::Upvalue(index),
+            None => Variable::Global(name.to_string()),
+        })
+    }
+
+    fn function(&mut self, body: &FunctionBody, has_self: bool) -> Result<Proto, CompileError> {
+        self.functions.push(FunctionState {
+            proto: Proto { is_vararg: body.is_vararg, ..Proto::default() },
+            constants: HashMap::new(),
+            locals: Vec::new(),
+            upvalue_names: Vec::new(),
+            blocks: Vec::new(),
+            loops: Vec::new(),
+            free: 1,
+        });
+        let params = has_self.then_some("self").into_iter().chain(body.params.iter().map(String::as_str));
+        for param in params {
+            let register = self.alloc();
+            self.declare(param, register)?;
+            self.state().proto.num_params += 1;
+        }
+        self.statements(&body.block)?;
+        self.emit(OpCode::Return, 1, 1, 0);
+        Ok(self.functions.pop().unwrap().proto)
+    }
+
+    fn closure(&mut self, body: &FunctionBody, has_self: bool, target: i64) -> Result<(), CompileError> {
+        let proto = self.function(body, has_self)?;
+        let protos = &mut self.state().proto.protos;
+        protos.push(proto);
+        let index = protos.len() as i64;
+        self.emit(OpCode::Closure, target, index, 0);
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), CompileError> {
+        self.enter_block();
+        self.statements(block)?;
+        self.exit_block();
+        Ok(())
+    }
+
+    fn statements(&mut self, block: &Block) -> Result<(), CompileError> {
+        block.statements.iter().try_for_each(|stmt| self.statement(stmt))
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
+        self.span = stmt.span;
+        let free = self.free();
+        match &stmt.kind {
+            StatementKind::LocalAssignment { names, exprs } => {
+                let base = self.free();
+                self.adjusted_list(exprs, names.len() as i64)?;
+                for (i, name) in names.iter().enumerate() {
+                    self.declare(name, base + i as i64)?;
+                }
+                // The new locals keep their registers.
+                return Ok(());
+            }
+            StatementKind::LocalFunction { name, body } => {
+                let register = self.alloc();
+                if self.declare(name, register)? {
+                    let value = self.alloc();
+                    self.closure(body, false, value)?;
+                    self.emit(OpCode::SetBox, register, value, 0);
+                    self.set_free(register + 1);
+                } else {
+                    self.closure(body, false, register)?;
+                }
+                return Ok(());
+            }
+            StatementKind::FunctionDeclaration { target, method, body } => {
+                let target = match method {
+                    Some(method) => {
+                        let object = self.any_register(target)?;
+                        Target::Index { object, key: -self.string_constant(method) }
+                    }
+                    None => self.target(target)?,
+                };
+                let value = self.alloc();
+                self.closure(body, method.is_some(), value)?;
+                self.store(target, value);
+            }
+            StatementKind::Assignment { targets, exprs } => self.assignment(targets, exprs)?,
+            StatementKind::Do(block) => self.block(block)?,
+            StatementKind::While { condition, block } => {
+                let start = self.pc();
+                let condition = self.any_register(condition)?;
+                self.set_free(free);
+                let exit = self.emit(OpCode::JmpIfNot, condition, 0, 0);
+                self.state().loops.push(Loop::default());
+                self.block(block)?;
+                let jump = self.emit(OpCode::Jmp, 0, 0, 0);
+                self.patch(jump, start);
+                let lp = self.state().loops.pop().unwrap();
+                for jump in lp.continues {
+                    self.patch(jump, start);
+                }
+                self.patch_here(lp.breaks.into_iter().chain([exit]));
+            }
+            StatementKind::Repeat { block, condition } => {
+                let start = self.pc();
+                self.state().loops.push(Loop::default());
+                // The condition is part of the loop body's scope.
+                self.enter_block();
+                self.statements(block)?;
+                let continues = std::mem::take(&mut self.state().loops.last_mut().unwrap().continues);
+                self.patch_here(continues);
+                let condition = self.any_register(condition)?;
+                let jump = self.emit(OpCode::JmpIfNot, condition, 0, 0);
+                self.patch(jump, start);
+                self.exit_block();
+                let lp = self.state().loops.pop().unwrap();
+                self.patch_here(lp.breaks);
+            }
+            StatementKind::If { clauses, else_block } => {
+                let mut exits = Vec::new();
+                for (i, (condition, block)) in clauses.iter().enumerate() {
+                    let condition = self.any_register(condition)?;
+                    self.set_free(free);
+                    let next = self.emit(OpCode::JmpIfNot, condition, 0, 0);
+                    self.block(block)?;
+                    if i + 1 < clauses.len() || else_block.is_some() {
+                        exits.push(self.emit(OpCode::Jmp, 0, 0, 0));
+                    }
+                    self.patch_here([next]);
+                }
+                if let Some(block) = else_block {
+                    self.block(block)?;
+                }
+                self.patch_here(exits);
+            }
+            StatementKind::NumericFor { var, start, limit, step, block } => {
+                let base = self.next_register(start)?;
+                self.next_register(limit)?;
+                match step {
+                    Some(step) => {
+                        self.next_register(step)?;
+                    }
+                    None => {
+                        let one = self.constant(Constant::Number(1.0));
+                        let register = self.alloc();
+                        self.emit(OpCode::LoadK, register, one, 0);
+                    }
+                }
+                let prep = self.emit(OpCode::ForPrep, base, 0, 0);
+                let body = self.pc();
+                self.state().loops.push(Loop::default());
+                self.enter_block();
+                let register = self.alloc();
+                self.declare(var, register)?;
+                self.statements(block)?;
+                self.exit_block();
+                self.patch_here([prep]);
+                let lp = self.state().loops.pop().unwrap();
+                self.patch_here(lp.continues);
+                self.emit(OpCode::ForLoop, base, body as i64, 0);
+                self.patch_here(lp.breaks);
+            }
+            StatementKind::GenericFor { names, exprs, block } => {
+                let base = self.free();
+                self.adjusted_list(exprs, 3)?;
+                let check = self.emit(OpCode::Jmp, 0, 0, 0);
+                let body = self.pc();
+                self.state().loops.push(Loop::default());
+                self.enter_block();
+                for name in names {
+                    let register = self.alloc();
+                    self.declare(name, register)?;
+                }
+                self.statements(block)?;
+                self.exit_block();
+                self.patch_here([check]);
+                let lp = self.state().loops.pop().unwrap();
+                self.patch_here(lp.continues);
+                self.emit(OpCode::TForLoop, base, body as i64, names.len() as i64);
+                self.patch_here(lp.breaks);
+            }
+            // `return f(x)` is a tail call, so that tail recursion does not
+            // grow the stack
+            StatementKind::Return(exprs) => match exprs.as_slice() {
+                [expr] if expr.is_call() => {
+                    let (base, b) = self.call_operands(expr)?;
+                    self.emit(OpCode::TailCall, base, b, 0);
+                }
+                _ => {
+                    let base = self.free();
+                    let count = self.open_list(exprs)?;
+                    self.emit(OpCode::Return, base, count, 0);
+                }
+            },
+            StatementKind::Break => {
+                let jump = self.emit(OpCode::Jmp, 0, 0, 0);
+                let Some(lp) = self.state().loops.last_mut() else {
+                    return Err(CompileError::new("no loop to break", stmt.span));
+                };
+                lp.breaks.push(jump);
+            }
+            StatementKind::Continue => {
+                let jump = self.emit(OpCode::Jmp, 0, 0, 0);
+                let Some(lp) = self.state().loops.last_mut() else {
+                    return Err(CompileError::new("no loop to continue", stmt.span));
+                };
+                lp.continues.push(jump);
+            }
+            StatementKind::Expression(expr) => {
+                self.call(expr, 1)?;
+            }
+        }
+        self.set_free(free);
+        Ok(())
+    }
+
+    fn assignment(&mut self, targets: &[Expression], exprs: &[Expression]) -> Result<(), CompileError> {
+        // Registers of locals that this statement assigns. Table and key
+        // operands held in them must be copied first, as in `t[1], t = 1, 2`.
+        let mut assigned = BTreeSet::new();
+        for target in targets {
+            if let ExpressionKind::Variable(name) = &target.kind
+                && let Variable::Local { register, boxed: false } = self.resolve(name)?
+            {
+                assigned.insert(register);
+            }
+        }
+        let mut resolved = Vec::with_capacity(targets.len());
+        for target in targets {
+            let mut target = self.target(target)?;
+            if let Target::Index { object, key } = &mut target {
+                for operand in [object, key] {
+                    if assigned.contains(operand) {
+                        let copy = self.alloc();
+                        self.emit(OpCode::Move, copy, *operand, 0);
+                        *operand = copy;
+                    }
+                }
+            }
+            resolved.push(target);
+        }
+        let base = self.free();
+        self.adjusted_list(exprs, targets.len() as i64)?;
+        for (i, target) in resolved.into_iter().enumerate().rev() {
+            self.store(target, base + i as i64);
+        }
+        Ok(())
+    }
+
+    /// Evaluate the operands of an assignment target.
+    fn target(&mut self, target: &Expression) -> Result<Target, CompileError> {
+        match &target.kind {
+            ExpressionKind::Variable(name) => Ok(Target::Variable(self.resolve(name)?)),
+            ExpressionKind::Index { object, key } => {
+                let object = self.any_register(object)?;
+                let key = self.rk(key)?;
+                Ok(Target::Index { object, key })
+            }
+            _ => Err(CompileError::new("cannot assign to this expression", target.span)),
+        }
+    }
+
+    fn store(&mut self, target: Target, value: i64) {
+        match target {
+            Target::Variable(Variable::Local { register, boxed: false }) => {
+                self.emit(OpCode::Move, register, value, 0);
+            }
+            Target::Variable(Variable::Local { register, boxed: true }) => {
+                self.emit(OpCode::SetBox, register, value, 0);
+            }
+            Target::Variable(Variable::Upvalue(index)) => {
+                self.emit(OpCode::SetUpval, value, index, 0);
+            }
+            Target::Variable(Variable::Global(name)) => {
+                let name = self.string_constant(&name);
+                self.emit(OpCode::SetGlobal, value, name, 0);
+            }
+            Target::Index { object, key } => {
+                self.emit(OpCode::SetTable, object, key, value);
+            }
+        }
+    }
+
+    /// Evaluate `exprs` into consecutive registers from the first free one,
+    /// adjusted to exactly `count` values. Afterwards these registers are in
+    /// use.
+    fn adjusted_list(&mut self, exprs: &[Expression], count: i64) -> Result<(), CompileError> {
+        let base = self.free();
+        for (i, expr) in exprs.iter().enumerate() {
+            let i = i as i64;
+            if i + 1 == exprs.len() as i64 && expr.is_multi_value() {
+                let wanted = (count - i).max(0);
+                self.multi_value(expr, wanted + 1)?;
+                self.set_free(base + count);
+                return Ok(());
+            }
+            self.next_register(expr)?;
+        }
+        let produced = exprs.len() as i64;
+        if produced < count {
+            self.emit(OpCode::LoadNil, base + produced, base + count - 1, 0);
+        }
+        self.set_free(base + count);
+        Ok(())
+    }
+
+    /// Evaluate `exprs` into consecutive registers, keeping all results of a
+    /// trailing call or `...`. Returns the `b` operand describing the count.
+    fn open_list(&mut self, exprs: &[Expression]) -> Result<i64, CompileError> {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i + 1 == exprs.len() && expr.is_multi_value() {
+                self.multi_value(expr, 0)?;
+                return Ok(0);
+            }
+            self.next_register(expr)?;
+        }
+        Ok(exprs.len() as i64 + 1)
+    }
+
+    /// Evaluate a call or `...` at the first free register, keeping `c - 1`
+    /// values, or all of them when `c` is `0`.
+    fn multi_value(&mut self, expr: &Expression, c: i64) -> Result<(), CompileError> {
+        if let ExpressionKind::Vararg = expr.kind {
+            let register = self.alloc();
+            self.emit(OpCode::Vararg, register, c, 0);
+            Ok(())
+        } else {
+            self.call(expr, c).map(|_| ())
+        }
+    }
+
+    /// Compile a call with the function at the first free register. Returns
+    /// that register, where the results are stored.
+    fn call(&mut self, expr: &Expression, c: i64) -> Result<i64, CompileError> {
+        let (base, b) = self.call_operands(expr)?;
+        self.emit(OpCode::Call, base, b, c);
+        self.set_free(base + (c - 1).max(0));
+        Ok(base)
+    }
+
+    /// Compile the function and arguments of the call `expr` into registers
+    /// from the top. Returns the register of the function and the `b`
+    /// operand of the call.
+    fn call_operands(&mut self, expr: &Expression) -> Result<(i64, i64), CompileError> {
+        let base = self.free();
+        let args = match &expr.kind {
+            ExpressionKind::Call { func, args } => {
+                self.next_register(func)?;
+                args
+            }
+            ExpressionKind::MethodCall { object, method, args } => {
+                let object = self.any_register(object)?;
+                let method = self.string_constant(method);
+                self.set_free(base + 2);
+                self.emit(OpCode::SelfOp, base, object, -method);
+                args
+            }
+            _ => unreachable!("call() on {:?}", expr.kind),
+        };
+        let b = self.open_list(args)?;
+        Ok((base, if b == 0 { 0 } else { self.free() - base }))
+    }
+
+    /// Compile `expr` into a new register at the top.
+    fn next_register(&mut self, expr: &Expression) -> Result<i64, CompileError> {
+        let register = self.alloc();
+        self.expression(expr, register)?;
+        Ok(register)
+    }
+
+    /// Register holding the value of `expr`; locals are used in place.
+    fn any_register(&mut self, expr: &Expression) -> Result<i64, CompileError> {
+        if let ExpressionKind::Variable(name) = &expr.kind
+            && let Variable::Local { register, boxed: false } = self.resolve(name)?
+        {
+            return Ok(register);
+        }
+        self.next_register(expr)
+    }
+
+    /// `RK` operand for `expr`: a constant for literals, a register otherwise.
+    fn rk(&mut self, expr: &Expression) -> Result<i64, CompileError> {
+        match &expr.kind {
+            ExpressionKind::Number(n) => Ok(-self.constant(Constant::Number(*n))),
+            ExpressionKind::String(s) => Ok(-self.string_constant(s)),
+            _ => self.any_register(expr),
+        }
+    }
+
+    /// Compile `expr` into `target`, which must already be allocated.
+    fn expression(&mut self, expr: &Expression, target: i64) -> Result<(), CompileError> {
+        let free = self.free();
+        match &expr.kind {
+            ExpressionKind::Nil => {
+                self.emit(OpCode::LoadNil, target, target, 0);
+            }
+            ExpressionKind::Boolean(b) => {
+                self.emit(OpCode::LoadBool, target, *b as i64, 0);
+            }
+            ExpressionKind::Number(n) => {
+                let index = self.constant(Constant::Number(*n));
+                self.emit(OpCode::LoadK, target, index, 0);
+            }
+            ExpressionKind::String(s) => {
+                let index = self.string_constant(s);
+                self.emit(OpCode::LoadK, target, index, 0);
+            }
+            ExpressionKind::Vararg => {
+                self.emit(OpCode::Vararg, target, 2, 0);
+            }
+            ExpressionKind::Variable(name) => match self.resolve(name)? {
+                Variable::Local { register, boxed: false } => {
+                    if register != target {
+                        self.emit(OpCode::Move, target, register, 0);
+                    }
+                }
+                Variable::Local { register, boxed: true } => {
+                    self.emit(OpCode::GetBox, target, register, 0);
+                }
+                Variable::Upvalue(index) => {
+                    self.emit(OpCode::GetUpval, target, index, 0);
+                }
+                Variable::Global(name) => {
+                    let name = self.string_constant(&name);
+                    self.emit(OpCode::GetGlobal, target, name, 0);
+                }
+            },
+            ExpressionKind::Function(body) => self.closure(body, false, target)?,
+            ExpressionKind::Table(fields) => {
+                // Positional values are collected in the registers above the
+                // table, so it has to be the topmost register.
+                if target + 1 == free {
+                    self.table(fields, target)?;
+                } else {
+                    let table = self.next_register(expr)?;
+                    self.emit(OpCode::Move, target, table, 0);
+                }
+            }
+            ExpressionKind::BinaryOp { left, op, right } if op == "and" || op == "or" => {
+                self.expression(left, target)?;
+                let skip = if op == "and" { OpCode::JmpIfNot } else { OpCode::JmpIf };
+                let jump = self.emit(skip, target, 0, 0);
+                self.expression(right, target)?;
+                self.patch_here([jump]);
+            }
+            ExpressionKind::BinaryOp { left, op, right } => {
+                let left = self.rk(left)?;
+                let right = self.rk(right)?;
+                match op.as_str() {
+                    ">" => self.emit(OpCode::Lt, target, right, left),
+                    ">=" => self.emit(OpCode::Le, target, right, left),
+                    _ => {
+                        let Some(opcode) = OpCode::from_binary_operator(op) else {
+                            return Err(CompileError::new(
+                                format!("unknown operator `{op}`"),
+                                expr.span,
+                            ));
+                        };
+                        self.emit(opcode, target, left, right)
+                    }
+                };
+            }
+            ExpressionKind::UnaryOp { op, operand } => {
+                let operand = self.any_register(operand)?;
+                let opcode = match op.as_str() {
+                    "-" => OpCode::Unm,
+                    "not" => OpCode::Not,
+                    "#" => OpCode::Len,
+                    _ => {
+                        return Err(CompileError::new(format!("unknown operator `{op}`"), expr.span));
+                    }
+                };
+                self.emit(opcode, target, operand, 0);
+            }
+            ExpressionKind::Paren(inner) => self.expression(inner, target)?,
+            ExpressionKind::Index { object, key } => {
+                let object = self.any_register(object)?;
+                let key = self.rk(key)?;
+                self.emit(OpCode::GetTable, target, object, key);
+            }
+            ExpressionKind::Call { .. } | ExpressionKind::MethodCall { .. } => {
+                let base = self.call(expr, 2)?;
+                if base != target {
+                    self.emit(OpCode::Move, target, base, 0);
+                }
+            }
+        }
+        self.set_free(free);
+        Ok(())
+    }
+
+    /// Fill the table in `table`, the topmost register.
+    fn table(&mut self, fields: &[TableField], table: i64) -> Result<(), CompileError> {
+        self.emit(OpCode::NewTable, table, 0, 0);
+        let mut pending = 0;
+        let mut next_index = 1;
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                TableField::Value(value) if i + 1 == fields.len() && value.is_multi_value() => {
+                    self.multi_value(value, 0)?;
+                    self.emit(OpCode::SetList, table, 0, next_index);
+                    pending = 0;
+                }
+                TableField::Value(value) => {
+                    self.next_register(value)?;
+                    pending += 1;
+                    if pending == FIELDS_PER_FLUSH {
+                        self.emit(OpCode::SetList, table, pending + 1, next_index);
+                        next_index += pending;
+                        pending = 0;
+                        self.set_free(table + 1);
+                    }
+                }
+                TableField::Named { name, value } => {
+                    let free = self.free();
+                    let key = -self.string_constant(name);
+                    let value = self.rk(value)?;
+                    self.emit(OpCode::SetTable, table, key, value);
+                    self.set_free(free);
+                }
+                TableField::Keyed { key, value } => {
+                    let free = self.free();
+                    let key = self.rk(key)?;
+                    let value = self.rk(value)?;
+                    self.emit(OpCode::SetTable, table, key, value);
+                    self.set_free(free);
+                }
+            }
+        }
+        if pending > 0 {
+            self.emit(OpCode::SetList, table, pending + 1, next_index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::lua::LuaVersion;
+    use crate::parser::parse;
+    use crate::scope;
+
+    fn compile_str(src: &str) -> Result<Proto, CompileError> {
+        let ast = parse(&tokenize(src, LuaVersion::Lua51).unwrap(), LuaVersion::Lua51).unwrap().ast;
+        compile(&ast, &scope::analyze(&ast))
+    }
+
+    #[test]
+    fn locals_use_registers() {
+        let proto = compile_str("local a, b = 1 local c = a + b").unwrap();
+        assert!(proto.is_vararg);
+        assert_eq!(proto.constants, [Constant::Number(1.0)]);
+        assert_eq!(
+            proto.code,
+            [
+                Instruction::new(OpCode::LoadK, 1, 1, 0),
+                Instruction::new(OpCode::LoadNil, 2, 2, 0),
+                Instruction::new(OpCode::Add, 3, 1, 2),
+                Instruction::new(OpCode::Return, 1, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn captured_locals_are_boxed() {
+        let proto = compile_str("local x = 1 local function f() x = x + 1 end").unwrap();
+        assert_eq!(proto.code[1], Instruction::new(OpCode::NewBox, 1, 1, 0));
+        let child = &proto.protos[0];
+        assert_eq!(child.upvalues, [UpvalueSource::Register(1)]);
+        assert!(child.code.contains(&Instruction::new(OpCode::SetUpval, 1, 1, 0)));
+    }
+
+    #[test]
+    fn break_outside_loop_is_error() {
+        let err = compile_str("local a = 1\nbreak").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 1));
+    }
+
+    #[test]
+    fn mismatched_scope_analysis_is_error() {
+        let ast = |src| parse(&tokenize(src, LuaVersion::Lua51).unwrap(), LuaVersion::Lua51).unwrap().ast;
+        let cases = [
+            ("local a = 1\nlocal b = a", "local a = 1\nlocal c = a", "declared `b` but the scope has `c`"),
+            ("local x = 1\nlocal function f() return x end", "local x = 1\nlocal function f() return 1 end", "captured local `x` is not boxed"),
+        ];
+        for (src, analyzed, expected) in cases {
+            let err = compile(&ast(src), &scope::analyze(&ast(analyzed))).unwrap_err();
+            assert!(err.message.contains(expected), "{err}");
+            assert_eq!(err.span.line, 2, "{err}");
+        }
+    }
+}
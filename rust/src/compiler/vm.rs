@@ -0,0 +1,314 @@
+//! Emits the interpreter for [`bytecode`](super::bytecode) programs as Lua
+//! AST.
+//!
+//! The emitted script stores every prototype as a nested table literal and
+//! runs it with a dispatch loop. Opcode numbers are drawn at random for every
+//! script and the handlers are selected by a binary search over them, so the
+//! layout of the interpreter differs between runs.
+
+use rand::Rng;
+use rand::seq::index;
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, Statement, StatementKind, TableField,
+};
+use crate::parser::parse_snippet;
+
+use super::bytecode::{Constant, Instruction, OpCode, Proto, UpvalueSource};
+
+/// Runtime support and the `run` function. `DISPATCH()` is replaced by the
+/// opcode handlers.
+///
+/// A prototype is `{code, constants, protos, num_params, is_vararg, upvalues}`
+/// where `code` holds four numbers per instruction and `upvalues` two numbers
+/// per upvalue: `1` and a register, or `0` and an upvalue index. The
+/// environment falls back to `_G` because `_ENV` is only special outside of
+/// the virtual machine, which matters when the interpreter is vmified again.
+const TEMPLATE: &str = r##"
+local unpack = unpack or table.unpack
+local select = select
+local env = _ENV or getfenv and getfenv() or _G
+local function pack(...)
+	return {n = select("#", ...), ...}
+end
+local run
+local function wrap(proto, upvals)
+	return function(...)
+		return run(proto, upvals, pack(...))
+	end
+end
+function run(proto, upvals, args)
+	local code, K, protos = proto[1], proto[2], proto[3]
+	local nparams = proto[4]
+	local R = {}
+	for i = 1, nparams do
+		R[i] = args[i]
+	end
+	local varargs = {n = 0}
+	if proto[5] and args.n > nparams then
+		varargs.n = args.n - nparams
+		for i = 1, varargs.n do
+			varargs[i] = args[nparams + i]
+		end
+	end
+	local top = 0
+	local pc = 1
+	while true do
+		local op, a, b, c = code[pc], code[pc + 1], code[pc + 2], code[pc + 3]
+		pc = pc + 4
+		DISPATCH()
+	end
+end
+"##;
+
+/// Lua source of the handler for `op`. `RK(x)` reads a register or constant
+/// operand, see [`parse_handler`].
+fn handler(op: OpCode) -> &'static str {
+    match op {
+        OpCode::Move => "R[a] = R[b]",
+        OpCode::LoadK => "R[a] = K[b]",
+        OpCode::LoadNil => "for i = a, b do R[i] = nil end",
+        OpCode::LoadBool => "R[a] = b == 1",
+        OpCode::GetGlobal => "R[a] = env[K[b]]",
+        OpCode::SetGlobal => "env[K[b]] = R[a]",
+        OpCode::GetUpval => "R[a] = upvals[b][1]",
+        OpCode::SetUpval => "upvals[b][1] = R[a]",
+        OpCode::NewBox => "R[a] = {R[b]}",
+        OpCode::GetBox => "R[a] = R[b][1]",
+        OpCode::SetBox => "R[a][1] = R[b]",
+        OpCode::GetTable => "R[a] = R[b][RK(c)]",
+        OpCode::SetTable => "R[a][RK(b)] = RK(c)",
+        OpCode::NewTable => "R[a] = {}",
+        OpCode::SetList => {
+            r#"
+            local n = b - 1
+            if b == 0 then n = top - a end
+            local t = R[a]
+            for i = 1, n do t[c + i - 1] = R[a + i] end
+            "#
+        }
+        OpCode::SelfOp => "local o = R[b] R[a + 1] = o R[a] = o[RK(c)]",
+        OpCode::Add => "R[a] = RK(b) + RK(c)",
+        OpCode::Sub => "R[a] = RK(b) - RK(c)",
+        OpCode::Mul => "R[a] = RK(b) * RK(c)",
+        OpCode::Div => "R[a] = RK(b) / RK(c)",
+        OpCode::Mod => "R[a] = RK(b) % RK(c)",
+        OpCode::Pow => "R[a] = RK(b) ^ RK(c)",
+        OpCode::Concat => "R[a] = RK(b) .. RK(c)",
+        OpCode::Eq => "R[a] = RK(b) == RK(c)",
+        OpCode::Ne => "R[a] = RK(b) ~= RK(c)",
+        OpCode::Lt => "R[a] = RK(b) < RK(c)",
+        OpCode::Le => "R[a] = RK(b) <= RK(c)",
+        OpCode::Unm => "R[a] = -R[b]",
+        OpCode::Not => "R[a] = not R[b]",
+        OpCode::Len => "R[a] = #R[b]",
+        OpCode::Jmp => "pc = a",
+        OpCode::JmpIf => "if R[a] then pc = b end",
+        OpCode::JmpIfNot => "if not R[a] then pc = b end",
+        OpCode::Call => {
+            r#"
+            local n = b - 1
+            if b == 0 then n = top - a end
+            local results = pack(R[a](unpack(R, a + 1, a + n)))
+            if c == 0 then
+                c = results.n + 1
+                top = a + results.n - 1
+            end
+            for i = 1, c - 1 do R[a + i - 1] = results[i] end
+            "#
+        }
+        OpCode::TailCall => {
+            r#"
+            local n = b - 1
+            if b == 0 then n = top - a end
+            return R[a](unpack(R, a + 1, a + n))
+            "#
+        }
+        OpCode::Return => {
+            r#"
+            local n = b - 1
+            if b == 0 then n = top - a + 1 end
+            return unpack(R, a, a + n - 1)
+            "#
+        }
+        OpCode::Vararg => {
+            r#"
+            local n = b - 1
+            if b == 0 then
+                n = varargs.n
+                top = a + n - 1
+            end
+            for i = 1, n do R[a + i - 1] = varargs[i] end
+            "#
+        }
+        OpCode::Closure => {
+            r#"
+            local p = protos[b]
+            local sources = p[6]
+            local captured = {}
+            for i = 1, #sources, 2 do
+                local index = sources[i + 1]
+                if sources[i] == 1 then
+                    captured[#captured + 1] = R[index]
+                else
+                    captured[#captured + 1] = upvals[index]
+                end
+            end
+            R[a] = wrap(p, captured)
+            "#
+        }
+        OpCode::ForPrep => "R[a] = R[a] - R[a + 2] pc = b",
+        OpCode::ForLoop => {
+            r#"
+            local step = R[a + 2]
+            local i = R[a] + step
+            R[a] = i
+            if step > 0 and i <= R[a + 1] or step <= 0 and i >= R[a + 1] then
+                R[a + 3] = i
+                pc = b
+            end
+            "#
+        }
+        OpCode::TForLoop => {
+            r#"
+            local results = pack(R[a](R[a + 1], R[a + 2]))
+            for i = 1, c do R[a + 2 + i] = results[i] end
+            if results[1] ~= nil then
+                R[a + 2] = results[1]
+                pc = b
+            end
+            "#
+        }
+    }
+}
+
+/// Emit a script that runs `proto` as its main chunk.
+pub fn emit(proto: &Proto, rng: &mut impl Rng) -> AstNode {
+    let numbers = index::sample(rng, 256, OpCode::ALL.len());
+    let mut opcodes: Vec<(OpCode, i64)> =
+        OpCode::ALL.iter().zip(numbers.iter()).map(|(&op, n)| (op, n as i64)).collect();
+    opcodes.sort_by_key(|&(_, number)| number);
+    let opcode_number = |op: OpCode| opcodes.iter().find(|&&(o, _)| o == op).unwrap().1;
+
+    let mut handlers: Vec<(i64, Vec<Statement>)> =
+        opcodes.iter().map(|&(op, number)| (number, parse_handler(op))).collect();
+    let mut dispatch = Some(dispatch(&mut handlers));
+
+    let mut ast = AstNode::new(parse_snippet(TEMPLATE));
+    fill_placeholder(&mut ast.block, &mut dispatch);
+    debug_assert!(dispatch.is_none(), "template lacks DISPATCH()");
+
+    let main = call(var("wrap"), vec![proto_literal(proto, &opcode_number), table(Vec::new())]);
+    let result = call(main, vec![ExpressionKind::Vararg.into()]);
+    ast.block.statements.push(StatementKind::Return(vec![result]).into());
+    ast
+}
+
+/// Parse the handler of `op`, expanding `RK(x)` to
+/// `(x < 0 and K[-x] or R[x])`. Constants are never `nil` or `false`, so the
+/// `and`/`or` idiom is exact.
+fn parse_handler(op: OpCode) -> Vec<Statement> {
+    let source = ["b", "c"].iter().fold(handler(op).to_string(), |source, x| {
+        source.replace(&format!("RK({x})"), &format!("({x} < 0 and K[-{x}] or R[{x}])"))
+    });
+    parse_snippet(&source).statements
+}
+
+/// Binary search over the sorted opcode numbers.
+fn dispatch(handlers: &mut [(i64, Vec<Statement>)]) -> Statement {
+    if let [(_, statements)] = handlers {
+        return StatementKind::Do(Block::new(std::mem::take(statements))).into();
+    }
+    let mid = handlers.len() / 2;
+    let pivot = handlers[mid].0;
+    let (low, high) = handlers.split_at_mut(mid);
+    let condition = ExpressionKind::BinaryOp {
+        left: Box::new(var("op")),
+        op: "<".to_string(),
+        right: Box::new(number(pivot as f64)),
+    };
+    StatementKind::If {
+        clauses: vec![(condition.into(), Block::new(vec![dispatch(low)]))],
+        else_block: Some(Block::new(vec![dispatch(high)])),
+    }
+    .into()
+}
+
+/// Replace the `DISPATCH()` statement in `block` or any nested block.
+fn fill_placeholder(block: &mut Block, replacement: &mut Option<Statement>) {
+    for stmt in block.statements.iter_mut() {
+        match &mut stmt.kind {
+            StatementKind::Expression(Expression {
+                kind: ExpressionKind::Call { func, .. },
+                ..
+            }) if matches!(&func.kind, ExpressionKind::Variable(name) if name == "DISPATCH") => {
+                if let Some(replacement) = replacement.take() {
+                    *stmt = replacement;
+                }
+            }
+            StatementKind::FunctionDeclaration { body, .. }
+            | StatementKind::LocalFunction { body, .. } => {
+                fill_placeholder(&mut body.block, replacement)
+            }
+            StatementKind::Do(block)
+            | StatementKind::While { block, .. }
+            | StatementKind::Repeat { block, .. }
+            | StatementKind::NumericFor { block, .. }
+            | StatementKind::GenericFor { block, .. } => fill_placeholder(block, replacement),
+            _ => {}
+        }
+    }
+}
+
+fn proto_literal(proto: &Proto, opcode: &impl Fn(OpCode) -> i64) -> Expression {
+    let code = proto.code.iter().flat_map(|instruction| encode(instruction, opcode)).map(number);
+    let constants = proto.constants.iter().map(|constant| match constant {
+        Constant::Number(n) => number(*n),
+        Constant::String(s) => ExpressionKind::String(s.clone()).into(),
+    });
+    let protos = proto.protos.iter().map(|child| proto_literal(child, opcode));
+    let upvalues = proto.upvalues.iter().flat_map(|source| match *source {
+        UpvalueSource::Register(register) => [1.0, register as f64],
+        UpvalueSource::Upvalue(index) => [0.0, index as f64],
+    });
+    table(vec![
+        table(code.collect()),
+        table(constants.collect()),
+        table(protos.collect()),
+        number(proto.num_params as f64),
+        ExpressionKind::Boolean(proto.is_vararg).into(),
+        table(upvalues.map(number).collect()),
+    ])
+}
+
+/// Numbers stored for `instruction`; jump targets become positions in the
+/// flattened code table.
+fn encode(instruction: &Instruction, opcode: &impl Fn(OpCode) -> i64) -> [f64; 4] {
+    let pc = |target: i64| (target * 4 + 1) as f64;
+    let Instruction { op, a, b, c } = *instruction;
+    match op {
+        OpCode::Jmp => [opcode(op) as f64, pc(a), b as f64, c as f64],
+        OpCode::JmpIf
+        | OpCode::JmpIfNot
+        | OpCode::ForPrep
+        | OpCode::ForLoop
+        | OpCode::TForLoop => [opcode(op) as f64, a as f64, pc(b), c as f64],
+        _ => [opcode(op) as f64, a as f64, b as f64, c as f64],
+    }
+}
+
+fn number(n: f64) -> Expression {
+    ExpressionKind::Number(n).into()
+}
+
+fn var(name: &str) -> Expression {
+    ExpressionKind::Variable(name.to_string()).into()
+}
+
+fn table(values: Vec<Expression>) -> Expression {
+    ExpressionKind::Table(values.into_iter().map(TableField::Value).collect()).into()
+}
+
+fn call(func: Expression, args: Vec<Expression>) -> Expression {
+    ExpressionKind::Call { func: Box::new(func), args }.into()
+}
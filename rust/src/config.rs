@@ -1,7 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::path::Path;
+use crate::error::PrometheusError;
 use crate::lua::LuaVersion;
 
+mod lua_table;
+
 /// Prometheus global configuration constants.
 pub const NAME: &str = "Prometheus";
 pub const REVISION: &str = "Alpha";
@@ -13,23 +18,27 @@ pub const IDENT_PREFIX: &str = "__prometheus_";
 pub const SPACE: &str = " ";
 pub const TAB: &str = "\t";
 
-#[derive(Debug, Clone, Deserialize)]
+/// Missing keys take their value from [`Config::default`]. Unknown keys are
+/// rejected so that misspelled keys are not ignored.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    #[serde(rename = "LuaVersion", default)]
+    #[serde(rename = "LuaVersion")]
     pub lua_version: LuaVersion,
-    #[serde(rename = "VarNamePrefix", default)]
+    #[serde(rename = "VarNamePrefix")]
     pub var_name_prefix: String,
-    #[serde(rename = "NameGenerator", default)]
+    #[serde(rename = "NameGenerator")]
     pub name_generator: String,
-    #[serde(rename = "PrettyPrint", default)]
+    #[serde(rename = "PrettyPrint")]
     pub pretty_print: bool,
-    #[serde(rename = "Seed", default)]
+    #[serde(rename = "Seed")]
     pub seed: u64,
-    #[serde(rename = "Steps", default)]
+    #[serde(rename = "Steps")]
     pub steps: Vec<Step>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Step {
     #[serde(rename = "Name")]
     pub name: String,
@@ -37,6 +46,27 @@ pub struct Step {
     pub settings: HashMap<String, serde_json::Value>,
 }
 
+/// File formats a [`Config`] can be read from and written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    /// A Lua chunk returning the config table, see [`Config::from_lua`].
+    Lua,
+}
+
+impl ConfigFormat {
+    /// Format of the file at `path`, selected by its extension.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "lua" => Some(ConfigFormat::Lua),
+            _ => None,
+        }
+    }
+}
 
 impl Default for Config {
     fn default() -> Self {
@@ -51,10 +81,113 @@ impl Default for Config {
     }
 }
 
-/// Load a built-in preset by name.
-pub fn load_preset(name: &str) -> Option<Config> {
-    match name {
-        "Minify" => Some(Config::default()),
-        _ => None,
+impl Config {
+    /// Parse a configuration from JSON text.
+    pub fn from_json(text: &str) -> Result<Config, PrometheusError> {
+        serde_json::from_str(text).map_err(|e| PrometheusError::Config(e.to_string()))
+    }
+
+    /// Parse a configuration from Lua source returning a table, the format
+    /// of the Lua implementation. The source is parsed but not run, so the
+    /// table may only contain tables, strings, numbers and booleans.
+    pub fn from_lua(text: &str) -> Result<Config, PrometheusError> {
+        let mut value = lua_table::parse_config(text)?;
+        // `Steps = {}` is read as an empty object
+        if let Some(steps) = value.get_mut("Steps")
+            && steps.as_object().is_some_and(|steps| steps.is_empty())
+        {
+            *steps = Value::Array(Vec::new());
+        }
+        serde_json::from_value(value).map_err(|e| PrometheusError::Config(e.to_string()))
+    }
+
+    /// Parse a configuration from TOML text.
+    pub fn from_toml(text: &str) -> Result<Config, PrometheusError> {
+        toml::from_str(text).map_err(|e| PrometheusError::Config(e.to_string()))
+    }
+
+    /// Parse a configuration written in `format`.
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Config, PrometheusError> {
+        match format {
+            ConfigFormat::Json => Config::from_json(text),
+            ConfigFormat::Toml => Config::from_toml(text),
+            ConfigFormat::Lua => Config::from_lua(text),
+        }
     }
+
+    /// Write the configuration in `format`. Settings are written in
+    /// alphabetical order, so the output does not depend on the hash order.
+    /// Lua configs cannot hold seeds above 2^53, as Lua numbers are doubles.
+    pub fn to_text(&self, format: ConfigFormat) -> Result<String, PrometheusError> {
+        let value = serde_json::to_value(self).map_err(|e| PrometheusError::Config(e.to_string()))?;
+        match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(&value)
+                .map(|text| text + "\n")
+                .map_err(|e| PrometheusError::Config(e.to_string())),
+            ConfigFormat::Toml => {
+                toml::to_string(&value).map_err(|e| PrometheusError::Config(e.to_string()))
+            }
+            ConfigFormat::Lua => lua_table::write_config(&value),
+        }
+    }
+}
+
+/// Names of the built-in presets, from the weakest to the strongest.
+const PRESETS: [&str; 4] = ["Minify", "Weak", "Medium", "Strong"];
+
+/// Names accepted by [`load_preset`].
+pub fn preset_names() -> &'static [&'static str] {
+    &PRESETS
+}
+
+/// Load a built-in preset by name. The presets match the ones of the Lua
+/// implementation.
+pub fn load_preset(name: &str) -> Option<Config> {
+    let constant_array = || {
+        step(
+            "ConstantArray",
+            json!({
+                "Treshold": 1,
+                "StringsOnly": true,
+                "Shuffle": true,
+                "Rotate": true,
+                "LocalWrapperTreshold": 0,
+            }),
+        )
+    };
+    let steps = match name {
+        "Minify" => vec![],
+        "Weak" => vec![
+            step("Vmify", json!({})),
+            step("ConstantArray", json!({ "Treshold": 1, "StringsOnly": true })),
+            step("WrapInFunction", json!({})),
+        ],
+        "Medium" => vec![
+            step("EncryptStrings", json!({})),
+            step("AntiTamper", json!({ "UseDebug": false })),
+            step("Vmify", json!({})),
+            constant_array(),
+            step("NumbersToExpressions", json!({})),
+            step("WrapInFunction", json!({})),
+        ],
+        "Strong" => vec![
+            step("Vmify", json!({})),
+            step("EncryptStrings", json!({})),
+            step("AntiTamper", json!({})),
+            step("Vmify", json!({})),
+            constant_array(),
+            step("NumbersToExpressions", json!({})),
+            step("WrapInFunction", json!({})),
+        ],
+        _ => return None,
+    };
+    Some(Config { steps, ..Config::default() })
+}
+
+fn step(name: &str, settings: Value) -> Step {
+    let settings = match settings {
+        Value::Object(settings) => settings.into_iter().collect(),
+        _ => HashMap::new(),
+    };
+    Step { name: name.to_string(), settings }
 }
@@ -0,0 +1,259 @@
+//! Reading and writing of configuration files written as Lua, the format
+//! used by the Lua implementation of Prometheus:
+//!
+//! ```lua
+//! return {
+//!     LuaVersion = "Lua51",
+//!     Steps = { { Name = "Vmify", Settings = {} } },
+//! }
+//! ```
+//!
+//! The file is parsed with the crate's own parser but never run, so the
+//! returned table may only contain tables, strings, numbers and booleans.
+
+use serde_json::{Map, Number, Value};
+
+use crate::ast::{Expression, ExpressionKind, Span, StatementKind, TableField};
+use crate::error::PrometheusError;
+use crate::lexer::tokenize;
+use crate::lua::LuaVersion;
+use crate::parser::parse;
+use crate::util::{escape, lua_bytes};
+
+/// The table returned by the Lua source `text` as JSON.
+pub(super) fn parse_config(text: &str) -> Result<Value, PrometheusError> {
+    let tokens = tokenize(text, LuaVersion::Lua51)?;
+    let ast = parse(&tokens, LuaVersion::Lua51)?.ast;
+    match ast.block.statements.as_slice() {
+        [statement] => match &statement.kind {
+            StatementKind::Return(exprs) if exprs.len() == 1 => value(&exprs[0]),
+            _ => Err(error(
+                statement.span,
+                "the config must consist of a single `return { ... }` statement",
+            )),
+        },
+        _ => Err(PrometheusError::Config(
+            "the config must consist of a single `return { ... }` statement".to_string(),
+        )),
+    }
+}
+
+fn value(expr: &Expression) -> Result<Value, PrometheusError> {
+    Ok(match &expr.kind {
+        ExpressionKind::Boolean(value) => Value::Bool(*value),
+        ExpressionKind::Number(value) => number(*value, expr.span)?,
+        ExpressionKind::String(value) => Value::String(text(value, expr.span)?),
+        ExpressionKind::UnaryOp { op, operand } if op == "-" => match operand.kind {
+            ExpressionKind::Number(value) => number(-value, expr.span)?,
+            _ => return Err(dynamic(expr)),
+        },
+        ExpressionKind::Paren(inner) => value(inner)?,
+        ExpressionKind::Table(fields) => table(fields, expr.span)?,
+        _ => return Err(dynamic(expr)),
+    })
+}
+
+/// Tables with only positional entries become arrays and tables with only
+/// string keys become objects. Empty tables become empty objects.
+fn table(fields: &[TableField], span: Span) -> Result<Value, PrometheusError> {
+    if !fields.is_empty() && fields.iter().all(|field| matches!(field, TableField::Value(_))) {
+        let items = fields.iter().map(|field| match field {
+            TableField::Value(item) => value(item),
+            _ => unreachable!(),
+        });
+        return items.collect::<Result<_, _>>().map(Value::Array);
+    }
+    let mut object = Map::new();
+    for field in fields {
+        let (key, item) = match field {
+            TableField::Named { name, value } => (name.clone(), value),
+            TableField::Keyed { key, value } => match &key.kind {
+                ExpressionKind::String(name) => (text(name, key.span)?, value),
+                _ => return Err(error(key.span, "table keys must be names or strings")),
+            },
+            TableField::Value(item) => {
+                return Err(error(item.span, "a table cannot mix list entries and named entries"));
+            }
+        };
+        if object.insert(key.clone(), value(item)?).is_some() {
+            return Err(error(span, format!("key {key:?} appears more than once")));
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+/// The lexer keeps the bytes of Lua strings, which are decoded as UTF-8.
+fn text(value: &str, span: Span) -> Result<String, PrometheusError> {
+    String::from_utf8(lua_bytes(value)).map_err(|_| error(span, "strings must be valid UTF-8"))
+}
+
+/// Lua numbers are doubles, which hold every whole number up to 2^53.
+const MAX_EXACT_INTEGER: u64 = 1 << 53;
+
+/// Whole numbers become JSON integers so that they can be read as integers.
+fn number(value: f64, span: Span) -> Result<Value, PrometheusError> {
+    if value.fract() == 0.0 && value.abs() <= MAX_EXACT_INTEGER as f64 {
+        return Ok(Value::from(value as i64));
+    }
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| error(span, format!("{value} is not a finite number")))
+}
+
+/// Lua source returning `config`, a JSON object. Integers above 2^53 are
+/// rejected, since Lua would read them back rounded.
+pub(super) fn write_config(config: &Value) -> Result<String, PrometheusError> {
+    let mut out = String::from("return ");
+    write_value(&mut out, config, 0)?;
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value, depth: usize) -> Result<(), PrometheusError> {
+    match value {
+        Value::Null => out.push_str("nil"),
+        Value::Bool(value) => out.push_str(&value.to_string()),
+        Value::Number(value) => {
+            let exact = match (value.as_u64(), value.as_i64()) {
+                (Some(value), _) => value <= MAX_EXACT_INTEGER,
+                (None, Some(value)) => value.unsigned_abs() <= MAX_EXACT_INTEGER,
+                (None, None) => true,
+            };
+            if !exact {
+                return Err(PrometheusError::Config(format!(
+                    "{value} cannot be written to a Lua config, Lua numbers only hold whole numbers up to 2^53"
+                )));
+            }
+            out.push_str(&value.to_string());
+        }
+        Value::String(value) => write_string(out, value),
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("{\n");
+            for item in items {
+                out.push_str(&"\t".repeat(depth + 1));
+                write_value(out, item, depth + 1)?;
+                out.push_str(",\n");
+            }
+            out.push_str(&"\t".repeat(depth));
+            out.push('}');
+        }
+        Value::Object(entries) if !entries.is_empty() => {
+            out.push_str("{\n");
+            for (key, item) in entries {
+                out.push_str(&"\t".repeat(depth + 1));
+                if is_name(key) {
+                    out.push_str(key);
+                } else {
+                    out.push('[');
+                    write_string(out, key);
+                    out.push(']');
+                }
+                out.push_str(" = ");
+                write_value(out, item, depth + 1)?;
+                out.push_str(",\n");
+            }
+            out.push_str(&"\t".repeat(depth));
+            out.push('}');
+        }
+        Value::Array(_) | Value::Object(_) => out.push_str("{}"),
+    }
+    Ok(())
+}
+
+/// Lua strings are byte strings, so UTF-8 text is written byte by byte.
+fn write_string(out: &mut String, value: &str) {
+    let bytes: String = value.bytes().map(char::from).collect();
+    out.push('"');
+    out.push_str(&escape(&bytes));
+    out.push('"');
+}
+
+fn is_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !LuaVersion::Lua51.conventions().keywords.contains(&key)
+}
+
+fn dynamic(expr: &Expression) -> PrometheusError {
+    let what = match &expr.kind {
+        ExpressionKind::Nil => "nil",
+        ExpressionKind::Vararg => "`...`",
+        ExpressionKind::Variable(_) => "a variable",
+        ExpressionKind::Function(_) => "a function",
+        ExpressionKind::BinaryOp { .. } | ExpressionKind::UnaryOp { .. } => "an operator",
+        ExpressionKind::Index { .. } => "an index expression",
+        ExpressionKind::Call { .. } | ExpressionKind::MethodCall { .. } => "a call",
+        _ => "this expression",
+    };
+    error(
+        expr.span,
+        format!("{what} is not supported, only tables, strings, numbers and booleans are"),
+    )
+}
+
+fn error(span: Span, message: impl Into<String>) -> PrometheusError {
+    PrometheusError::Config(format!(
+        "line {}, column {}: {}",
+        span.line,
+        span.column,
+        message.into()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn literal_tables_become_json() {
+        let source = r#"
+            -- Upstream style config
+            return {
+                LuaVersion = "LuaU";
+                Seed = 3,
+                ["PrettyPrint"] = true,
+                Steps = { { Name = "Vmify", Settings = {} }, { Name = "X", Settings = { A = -0.5 } } },
+            }
+        "#;
+        assert_eq!(
+            parse_config(source).unwrap(),
+            json!({
+                "LuaVersion": "LuaU",
+                "Seed": 3,
+                "PrettyPrint": true,
+                "Steps": [{ "Name": "Vmify", "Settings": {} }, { "Name": "X", "Settings": { "A": -0.5 } }],
+            })
+        );
+    }
+
+    #[test]
+    fn written_tables_read_back() {
+        let config = json!({
+            "Seed": 3,
+            "Steps": [{ "Name": "Watermark", "Settings": { "Content": "caf\u{e9} \"\n", "and": 0.5 } }],
+            "Empty": {},
+        });
+        let source = write_config(&config).unwrap();
+        assert!(source.contains("\t\t\tContent = \"caf\\195\\169 \\\"\\n\",\n"), "{source}");
+        assert!(source.contains("[\"and\"] = 0.5"), "{source}");
+        assert_eq!(parse_config(&source).unwrap(), config);
+    }
+
+    #[test]
+    fn dynamic_content_is_rejected() {
+        let cases = [
+            ("return { Seed = os.time() }", "line 1, column 17: a call is not supported"),
+            ("return { Seed = 1 + 2 }", "line 1, column 17: an operator is not supported"),
+            ("return { Name = name }", "line 1, column 17: a variable is not supported"),
+            ("return { 1, A = 2 }", "line 1, column 10: a table cannot mix"),
+            ("return { [1] = 2 }", "line 1, column 11: table keys must be names or strings"),
+            ("local c = {} return c", "the config must consist of a single"),
+        ];
+        for (source, expected) in cases {
+            let err = parse_config(source).unwrap_err().to_string();
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Error type shared by the public API.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::ParseError;
+use crate::lexer::LexError;
+
+/// Errors produced while configuring or running the obfuscator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrometheusError {
+    /// The configuration could not be read or is invalid.
+    Config(String),
+    /// A step name in the configuration is not registered.
+    UnknownStep(String),
+    /// The configured name generator does not exist.
+    UnknownNameGenerator(String),
+    /// A setting of a configured step is unknown or has an invalid value.
+    InvalidSetting { step: String, setting: String, message: String },
+    /// The input could not be tokenized.
+    Lex(LexError),
+    /// The input is not valid Lua.
+    Parse(ParseError),
+    /// A step could not transform the AST.
+    Step { step: String, message: String },
+}
+
+impl PrometheusError {
+    /// Create a [`PrometheusError::Step`] for the step called `step`.
+    pub fn step(step: impl Into<String>, message: impl Into<String>) -> Self {
+        PrometheusError::Step { step: step.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for PrometheusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrometheusError::Config(message) => write!(f, "invalid config: {message}"),
+            PrometheusError::UnknownStep(name) => write!(f, "step {name} not registered"),
+            PrometheusError::UnknownNameGenerator(name) => {
+                write!(f, "unknown name generator {name}")
+            }
+            PrometheusError::InvalidSetting { step, setting, message } => {
+                write!(f, "invalid setting {setting} of step {step}: {message}")
+            }
+            PrometheusError::Lex(err) => write!(f, "{err}"),
+            PrometheusError::Parse(err) => write!(f, "{err}"),
+            PrometheusError::Step { step, message } => write!(f, "step {step} failed: {message}"),
+        }
+    }
+}
+
+impl Error for PrometheusError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PrometheusError::Lex(err) => Some(err),
+            PrometheusError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for PrometheusError {
+    fn from(err: ParseError) -> Self {
+        PrometheusError::Parse(err)
+    }
+}
+
+impl From<LexError> for PrometheusError {
+    fn from(err: LexError) -> Self {
+        PrometheusError::Lex(err)
+    }
+}
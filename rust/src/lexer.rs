@@ -1,10 +1,11 @@
 //! Tokenizer for Lua source code.
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use crate::config;
 use crate::logger::Logger;
-use crate::lua::{LuaConventions, LuaVersion};
+use crate::lua::LuaVersion;
 use crate::util::{chararray, escape, lookupify};
 
 /// Kinds of tokens produced by the lexer.
@@ -38,6 +39,28 @@ pub struct Token {
     pub annotations: Vec<String>,
 }
 
+/// Tokenizing failed at the given position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl LexError {
+    pub fn new(message: impl Into<String>, line: usize, column: usize) -> Self {
+        Self { message: message.into(), line, column }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lexing Error at Position {}:{}, {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for LexError {}
+
 struct Position {
     line: usize,
     column: usize,
@@ -47,7 +70,6 @@ struct Lexer<'a> {
     input: &'a [u8],
     index: usize,
     length: usize,
-    conv: &'static LuaConventions,
     logger: Logger,
 
     // lookup tables
@@ -136,7 +158,6 @@ impl<'a> Lexer<'a> {
             input: input.as_bytes(),
             index: 0,
             length: input.len(),
-            conv,
             logger: Logger::default(),
             number_chars,
             hex_number_chars,
@@ -201,64 +222,49 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn get(&mut self) -> char {
+    fn get(&mut self) -> Result<char, LexError> {
         if self.index >= self.length {
-            self.logger
-                .error(self.generate_error("Unexpected end of input"));
+            return Err(self.generate_error("Unexpected end of input"));
         }
         let ch = self.input[self.index] as char;
         self.index += 1;
-        ch
-    }
-
-    fn expect_char(&mut self, c: char) -> char {
-        let ch = self.peek(0);
-        if ch != c {
-            self.logger
-                .error(self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string()))));
-        }
-        self.index += 1;
-        ch
+        Ok(ch)
     }
 
-    fn expect_number_start(&mut self) -> char {
+    /// Consume the current char if it is in `set`, otherwise fail.
+    fn expect_set(&mut self, set: &HashSet<char>) -> Result<char, LexError> {
         let ch = self.peek(0);
-        if ch != '.' && !self.number_chars.contains(&ch) {
-            self.logger
-                .error(self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string()))));
+        if !set.contains(&ch) {
+            return Err(self.unexpected_char(ch));
         }
         self.index += 1;
-        ch
+        Ok(ch)
     }
 
-    fn expect_ident_char(&mut self) -> char {
+    fn expect_char(&mut self, c: char) -> Result<char, LexError> {
         let ch = self.peek(0);
-        if !self.ident_chars.contains(&ch) {
-            self.logger
-                .error(self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string()))));
+        if ch != c {
+            return Err(self.unexpected_char(ch));
         }
         self.index += 1;
-        ch
+        Ok(ch)
     }
 
-    fn expect_string_start(&mut self) -> char {
+    fn expect_number_start(&mut self) -> Result<char, LexError> {
         let ch = self.peek(0);
-        if !self.string_start.contains(&ch) {
-            self.logger
-                .error(self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string()))));
+        if ch != '.' && !self.number_chars.contains(&ch) {
+            return Err(self.unexpected_char(ch));
         }
         self.index += 1;
-        ch
+        Ok(ch)
     }
 
-    fn expect_hex_digit(&mut self) -> char {
-        let ch = self.peek(0);
-        if !self.hex_number_chars.contains(&ch) {
-            self.logger
-                .error(self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string()))));
+    fn unexpected_char(&self, ch: char) -> LexError {
+        if self.index >= self.length {
+            self.generate_error("Unexpected end of input")
+        } else {
+            self.generate_error(&format!("Unexpected char '{}'", escape(&ch.to_string())))
         }
-        self.index += 1;
-        ch
     }
 
     fn is_char(&self, ch: char, n: usize) -> bool {
@@ -269,27 +275,28 @@ impl<'a> Lexer<'a> {
         set.contains(&self.peek(n))
     }
 
-    fn parse_annotation(&mut self) -> Option<char> {
+    fn parse_annotation(&mut self) -> Result<Option<char>, LexError> {
         let ch = self.peek(0);
         if self.annotation_start.contains(&ch) {
             self.index += 1;
             let mut buf = String::new();
             while self.is_set(&self.annotation_chars, 0) {
-                buf.push(self.get());
+                buf.push(self.get()?);
             }
             if !buf.is_empty() {
                 self.annotations.push(buf.to_lowercase());
             }
-            None
+            Ok(None)
         } else if self.index < self.length {
-            Some(self.get())
+            Ok(Some(self.get()?))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn skip_comment(&mut self) -> bool {
+    fn skip_comment(&mut self) -> Result<bool, LexError> {
         if self.is_char('-', 0) && self.is_char('-', 1) {
+            let start = self.index;
             self.index += 2;
             if self.is_char('[', 0) {
                 self.index += 1;
@@ -301,46 +308,48 @@ impl<'a> Lexer<'a> {
                 if self.is_char('[', 0) {
                     self.index += 1;
                     loop {
-                        if let Some(ch) = self.parse_annotation() {
-                            if ch == ']' {
-                                let mut eq2 = 0;
-                                while self.is_char('=', 0) {
-                                    self.index += 1;
-                                    eq2 += 1;
-                                }
-                                if self.is_char(']', 0) && eq2 == eq_count {
-                                    self.index += 1;
-                                    return true;
-                                }
+                        if self.index >= self.length {
+                            return Err(self.error_at(start, "Unterminated long comment"));
+                        }
+                        if let Some(']') = self.parse_annotation()? {
+                            let mut eq2 = 0;
+                            while self.is_char('=', 0) {
+                                self.index += 1;
+                                eq2 += 1;
+                            }
+                            if self.is_char(']', 0) && eq2 == eq_count {
+                                self.index += 1;
+                                return Ok(true);
                             }
                         }
                     }
                 }
             }
             while self.index < self.length {
-                match self.parse_annotation() {
-                    Some('\n') => break,
-                    _ => {}
+                if let Some('\n') = self.parse_annotation()? {
+                    break;
                 }
             }
-            return true;
+            return Ok(true);
         }
-        false
+        Ok(false)
     }
 
-    fn skip_whitespace_and_comments(&mut self) {
-        while self.skip_comment() {}
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), LexError> {
+        while self.skip_comment()? {}
         while self.is_set(&self.whitespace, 0) {
             self.index += 1;
-            while self.skip_comment() {}
+            while self.skip_comment()? {}
         }
+        Ok(())
     }
 
     fn int(&mut self, chars: &HashSet<char>, seps: Option<&HashSet<char>>) -> String {
         let mut buf = String::new();
         loop {
             if self.is_set(chars, 0) {
-                buf.push(self.get());
+                buf.push(self.peek(0));
+                self.index += 1;
             } else if let Some(s) = seps {
                 if s.contains(&self.peek(0)) {
                     self.index += 1;
@@ -354,9 +363,23 @@ impl<'a> Lexer<'a> {
         buf
     }
 
-    fn number(&mut self) -> Token {
+    /// Parse the digits of a binary or hexadecimal literal.
+    fn radix_digits(&mut self, start: usize, chars: &HashSet<char>, radix: u32) -> Result<Token, LexError> {
+        let seps = self.decimal_separators.clone();
+        let digits = self.int(chars, seps.as_ref());
+        if digits.is_empty() {
+            return Err(self.generate_error("Malformed number"));
+        }
+        // Accumulate in floating point so that oversized literals do not overflow.
+        let value = digits
+            .chars()
+            .fold(0.0, |acc, d| acc * radix as f64 + d.to_digit(radix).unwrap() as f64);
+        Ok(self.token(start, TokenKind::Number, TokenValue::Number(value)))
+    }
+
+    fn number(&mut self) -> Result<Token, LexError> {
         let start = self.index;
-        let first = self.expect_number_start();
+        let first = self.expect_number_start()?;
         let mut source = first.to_string();
         let seps = self.decimal_separators.clone();
 
@@ -364,16 +387,12 @@ impl<'a> Lexer<'a> {
             if self.binary_nums.contains(&self.peek(0)) {
                 self.index += 1;
                 let binary_chars = self.binary_number_chars.clone();
-                let digits = self.int(&binary_chars, seps.as_ref());
-                let value = i64::from_str_radix(&digits, 2).unwrap() as f64;
-                return self.token(start, TokenKind::Number, TokenValue::Number(value));
+                return self.radix_digits(start, &binary_chars, 2);
             }
             if self.hexadecimal_nums.contains(&self.peek(0)) {
                 self.index += 1;
                 let hex_chars = self.hex_number_chars.clone();
-                let digits = self.int(&hex_chars, seps.as_ref());
-                let value = i64::from_str_radix(&digits, 16).unwrap() as f64;
-                return self.token(start, TokenKind::Number, TokenValue::Number(value));
+                return self.radix_digits(start, &hex_chars, 16);
             }
         }
 
@@ -384,40 +403,40 @@ impl<'a> Lexer<'a> {
             let number_chars = self.number_chars.clone();
             source.push_str(&self.int(&number_chars, seps.as_ref()));
             if self.is_char('.', 0) {
-                source.push(self.get());
+                source.push(self.get()?);
                 let number_chars = self.number_chars.clone();
                 source.push_str(&self.int(&number_chars, seps.as_ref()));
             }
         }
 
         if self.decimal_exponent.contains(&self.peek(0)) {
-            source.push(self.get());
+            source.push(self.get()?);
             if self.peek(0) == '+' || self.peek(0) == '-' {
-                source.push(self.get());
+                source.push(self.get()?);
             }
             let number_chars = self.number_chars.clone();
             let exp = self.int(&number_chars, seps.as_ref());
             if exp.is_empty() {
-                self.logger
-                    .error(self.generate_error("Expected a valid exponent"));
+                return Err(self.generate_error("Expected a valid exponent"));
             }
             source.push_str(&exp);
         }
 
         let value: f64 = source.parse().unwrap_or(0.0);
-        self.token(start, TokenKind::Number, TokenValue::Number(value))
+        Ok(self.token(start, TokenKind::Number, TokenValue::Number(value)))
     }
 
-    fn ident(&mut self) -> Token {
+    fn ident(&mut self) -> Result<Token, LexError> {
         let start = self.index;
         let mut source = String::new();
-        source.push(self.expect_ident_char());
+        let ident_chars = self.ident_chars.clone();
+        source.push(self.expect_set(&ident_chars)?);
         while self.is_set(&self.ident_chars, 0) {
-            source.push(self.get());
+            source.push(self.get()?);
         }
 
         if self.keywords.contains(source.as_str()) {
-            self.token(start, TokenKind::Keyword, TokenValue::String(source))
+            Ok(self.token(start, TokenKind::Keyword, TokenValue::String(source)))
         } else {
             let tk = self.token(start, TokenKind::Ident, TokenValue::String(source.clone()));
             if source.starts_with(config::IDENT_PREFIX) {
@@ -426,52 +445,59 @@ impl<'a> Lexer<'a> {
                     tk.line, tk.column, config::IDENT_PREFIX
                 ));
             }
-            tk
+            Ok(tk)
         }
     }
 
-    fn single_line_string(&mut self) -> Token {
+    fn single_line_string(&mut self) -> Result<Token, LexError> {
         let start = self.index;
-        let start_char = self.expect_string_start();
+        let string_start = self.string_start.clone();
+        let start_char = self.expect_set(&string_start)?;
         let mut buf = String::new();
         loop {
             if self.is_char(start_char, 0) {
                 break;
             }
-            let mut ch = self.get();
+            if self.index >= self.length {
+                return Err(self.error_at(start, "Unterminated String"));
+            }
+            let mut ch = self.get()?;
             if ch == '\n' {
                 self.index -= 1;
-                self.logger
-                    .error(self.generate_error("Unterminated String"));
+                return Err(self.generate_error("Unterminated String"));
             }
             if ch == '\\' {
-                ch = self.get();
+                ch = self.get()?;
                 if let Some(&e) = self.escape_sequences.get(&ch) {
                     ch = e;
                 } else if self.numerical_escapes && self.number_chars.contains(&ch) {
                     let mut num = ch.to_string();
                     if self.number_chars.contains(&self.peek(0)) {
-                        num.push(self.get());
+                        num.push(self.get()?);
                     }
                     if self.number_chars.contains(&self.peek(0)) {
-                        num.push(self.get());
+                        num.push(self.get()?);
                     }
-                    let value = num.parse::<u8>().unwrap();
+                    let value = num
+                        .parse::<u8>()
+                        .map_err(|_| self.generate_error("Escape sequence too large"))?;
                     ch = value as char;
                 } else if self.unicode_escapes && ch == 'u' {
-                    self.expect_char('{');
+                    self.expect_char('{')?;
                     let mut num = String::new();
                     while self.is_set(&self.hex_number_chars, 0) {
-                        num.push(self.get());
+                        num.push(self.get()?);
                     }
-                    self.expect_char('}');
-                    let code = u32::from_str_radix(&num, 16).unwrap();
+                    self.expect_char('}')?;
+                    let code = u32::from_str_radix(&num, 16)
+                        .map_err(|_| self.generate_error("Invalid unicode escape"))?;
                     ch = std::char::from_u32(code).unwrap_or('\u{FFFD}');
                 } else if self.hex_escapes && ch == 'x' {
+                    let hex_chars = self.hex_number_chars.clone();
                     let h = format!(
                         "{}{}",
-                        self.expect_hex_digit(),
-                        self.expect_hex_digit()
+                        self.expect_set(&hex_chars)?,
+                        self.expect_set(&hex_chars)?
                     );
                     let value = u8::from_str_radix(&h, 16).unwrap();
                     ch = value as char;
@@ -484,11 +510,11 @@ impl<'a> Lexer<'a> {
             }
             buf.push(ch);
         }
-        self.expect_char(start_char);
-        self.token(start, TokenKind::String, TokenValue::String(buf))
+        self.expect_char(start_char)?;
+        Ok(self.token(start, TokenKind::String, TokenValue::String(buf)))
     }
 
-    fn multi_line_string(&mut self) -> Option<Token> {
+    fn multi_line_string(&mut self) -> Result<Option<Token>, LexError> {
         let start = self.index;
         if self.is_char('[', 0) {
             self.index += 1;
@@ -504,7 +530,10 @@ impl<'a> Lexer<'a> {
                 }
                 let mut value = String::new();
                 loop {
-                    let ch = self.get();
+                    if self.index >= self.length {
+                        return Err(self.error_at(start, "Unterminated long string"));
+                    }
+                    let ch = self.get()?;
                     if ch == ']' {
                         let mut eq2 = 0;
                         while self.is_char('=', 0) {
@@ -513,9 +542,10 @@ impl<'a> Lexer<'a> {
                         }
                         if self.is_char(']', 0) && eq2 == eq_count {
                             self.index += 1;
-                            return Some(self.token(start, TokenKind::String, TokenValue::String(value)));
+                            return Ok(Some(self.token(start, TokenKind::String, TokenValue::String(value))));
                         } else {
                             value.push(ch);
+                            value.extend(std::iter::repeat_n('=', eq2));
                         }
                     } else {
                         value.push(ch);
@@ -524,26 +554,27 @@ impl<'a> Lexer<'a> {
             }
         }
         self.index = start;
-        None
+        Ok(None)
     }
 
-    fn symbol(&mut self) -> Token {
+    fn symbol(&mut self) -> Result<Token, LexError> {
         let start = self.index;
         for len in (1..=self.max_symbol_length).rev() {
             if self.index + len <= self.length {
-                let s = std::str::from_utf8(&self.input[self.index..self.index + len]).unwrap();
+                let Ok(s) = std::str::from_utf8(&self.input[self.index..self.index + len]) else {
+                    continue;
+                };
                 if self.symbols.contains(s) {
                     self.index += len;
-                    return self.token(start, TokenKind::Symbol, TokenValue::String(s.to_string()));
+                    return Ok(self.token(start, TokenKind::Symbol, TokenValue::String(s.to_string())));
                 }
             }
         }
-        self.logger
-            .error(self.generate_error("Unknown Symbol"));
+        Err(self.generate_error("Unknown Symbol"))
     }
 
     fn token(&mut self, start: usize, kind: TokenKind, value: TokenValue) -> Token {
-        let (line, column) = self.get_position(self.index);
+        let (line, column) = self.get_position(start);
         let source = if self.index >= start && self.index <= self.length {
             String::from_utf8_lossy(&self.input[start..self.index]).into_owned()
         } else {
@@ -562,20 +593,24 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn generate_error(&self, msg: &str) -> String {
-        let (line, column) = self.get_position(self.index);
-        format!("Lexing Error at Position {}:{}, {}", line, column, msg)
+    fn generate_error(&self, msg: &str) -> LexError {
+        self.error_at(self.index, msg)
     }
 
-    fn next_token(&mut self) -> Token {
-        self.skip_whitespace_and_comments();
+    fn error_at(&self, idx: usize, msg: &str) -> LexError {
+        let (line, column) = self.get_position(idx);
+        LexError::new(msg, line, column)
+    }
+
+    fn next_token(&mut self) -> Result<Token, LexError> {
+        self.skip_whitespace_and_comments()?;
         let start = self.index;
         if start >= self.length {
-            return self.token(
+            return Ok(self.token(
                 start,
                 TokenKind::Eof,
                 TokenValue::String("<EOF>".to_string()),
-            );
+            ));
         }
 
         if self.is_set(&self.number_chars, 0) {
@@ -590,10 +625,10 @@ impl<'a> Lexer<'a> {
             return self.single_line_string();
         }
 
-        if self.is_char('[', 0) {
-            if let Some(tk) = self.multi_line_string() {
-                return tk;
-            }
+        if self.is_char('[', 0)
+            && let Some(tk) = self.multi_line_string()?
+        {
+            return Ok(tk);
         }
 
         if self.is_char('.', 0) && self.is_set(&self.number_chars, 1) {
@@ -604,7 +639,7 @@ impl<'a> Lexer<'a> {
             return self.symbol();
         }
 
-        self.logger.error(self.generate_error(&format!(
+        Err(self.generate_error(&format!(
             "Unexpected char \"{}\"!",
             escape(&self.peek(0).to_string())
         )))
@@ -612,17 +647,55 @@ impl<'a> Lexer<'a> {
 }
 
 /// Convert Lua source code into a sequence of tokens.
-pub fn tokenize(input: &str, version: LuaVersion) -> Vec<Token> {
+pub fn tokenize(input: &str, version: LuaVersion) -> Result<Vec<Token>, LexError> {
     let mut lexer = Lexer::new(input, version);
     let mut tokens = Vec::new();
     loop {
-        let tk = lexer.next_token();
+        let tk = lexer.next_token()?;
         let end = tk.kind == TokenKind::Eof;
         tokens.push(tk);
         if end {
             break;
         }
     }
-    tokens
+    Ok(tokens)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex_error(src: &str) -> LexError {
+        tokenize(src, LuaVersion::Lua51).unwrap_err()
+    }
+
+    #[test]
+    fn unexpected_char_is_error() {
+        let err = lex_error("local a = 1\nlocal b = $");
+        assert_eq!((err.line, err.column), (2, 11));
+        assert!(err.message.contains('$'));
+    }
+
+    #[test]
+    fn unterminated_strings_and_comments() {
+        assert!(lex_error("x = 'abc").message.contains("Unterminated String"));
+        assert!(lex_error("x = 'abc\n'").message.contains("Unterminated String"));
+        assert!(lex_error("x = [[abc").message.contains("Unterminated long string"));
+        let err = lex_error("x = 1\n--[==[ never closed ]]");
+        assert!(err.message.contains("Unterminated long comment"));
+        assert_eq!((err.line, err.column), (2, 1));
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        assert!(lex_error("x = 0x").message.contains("Malformed number"));
+        assert!(lex_error("x = 1e").message.contains("exponent"));
+        assert!(lex_error("x = '\\999'").message.contains("too large"));
+    }
+
+    #[test]
+    fn long_strings_keep_nested_brackets() {
+        let tokens = tokenize("x = [==[a]]b]=]c]==]", LuaVersion::Lua51).unwrap();
+        assert_eq!(tokens[2].value, TokenValue::String("a]]b]=]c".into()));
+    }
+}
@@ -2,7 +2,9 @@
 
 pub mod ast;
 pub mod colors;
+pub mod compiler;
 pub mod config;
+pub mod error;
 pub mod lexer;
 pub mod logger;
 pub mod lua;
@@ -14,9 +16,15 @@ pub mod steps;
 pub mod pipeline;
 pub mod random_literals;
 pub mod random_strings;
+pub mod renamer;
+pub mod schema;
+pub mod scope;
+pub mod unparser;
 pub mod util;
+pub mod visitor;
 
-pub use config::{Config, load_preset};
+pub use config::{Config, ConfigFormat, load_preset, preset_names};
+pub use error::PrometheusError;
 pub use logger::{LogLevel, Logger};
 pub use lua::{LuaConventions, LuaVersion};
 pub use obfuscator::obfuscate;
@@ -25,6 +33,7 @@ pub use pipeline::Pipeline;
 #[cfg(test)]
 mod tests {
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn placeholder() {
         assert!(true);
     }
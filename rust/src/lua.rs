@@ -1,18 +1,13 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Supported Lua language versions.
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
 pub enum LuaVersion {
+    #[default]
     Lua51,
     LuaU,
 }
 
-impl Default for LuaVersion {
-    fn default() -> Self {
-        LuaVersion::Lua51
-    }
-}
-
 /// Language conventions for a particular [`LuaVersion`].
 #[derive(Debug, Clone)]
 pub struct LuaConventions {
@@ -36,6 +31,17 @@ pub struct LuaConventions {
 }
 
 impl LuaVersion {
+    /// Every supported version.
+    pub const ALL: [LuaVersion; 2] = [LuaVersion::Lua51, LuaVersion::LuaU];
+
+    /// Name of the version in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LuaVersion::Lua51 => "Lua51",
+            LuaVersion::LuaU => "LuaU",
+        }
+    }
+
     /// Get the conventions associated with this Lua version.
     pub fn conventions(&self) -> &'static LuaConventions {
         match self {
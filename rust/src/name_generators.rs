@@ -8,6 +8,7 @@ const MANGLED_VAR_DIGITS: &[u8] =
 const MANGLED_VAR_START: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
 
 /// Generates simple increasing identifiers in the form `_1`, `_2`, …
+#[derive(Default)]
 pub struct NumberGenerator {
     counter: u64,
 }
@@ -26,6 +27,7 @@ impl NameGenerator for NumberGenerator {
 }
 
 /// Mimics the `mangled.lua` name generator from the Lua implementation.
+#[derive(Default)]
 pub struct MangledGenerator {
     counter: u64,
 }
@@ -39,23 +41,27 @@ impl MangledGenerator {
 impl NameGenerator for MangledGenerator {
     fn generate(&mut self) -> String {
         self.counter += 1;
-        let mut id = self.counter;
-        let base_start = MANGLED_VAR_START.len() as u64;
-        let base_digits = MANGLED_VAR_DIGITS.len() as u64;
+        mangled_name(self.counter)
+    }
+}
 
-        let mut name = String::new();
-        let d = (id % base_start) as usize;
-        id = (id - d as u64) / base_start;
-        name.push(MANGLED_VAR_START[d] as char);
+/// The name [`MangledGenerator`] produces for the `id`th variable.
+pub fn mangled_name(mut id: u64) -> String {
+    let base_start = MANGLED_VAR_START.len() as u64;
+    let base_digits = MANGLED_VAR_DIGITS.len() as u64;
 
-        while id > 0 {
-            let d = (id % base_digits) as usize;
-            id = (id - d as u64) / base_digits;
-            name.push(MANGLED_VAR_DIGITS[d] as char);
-        }
+    let mut name = String::new();
+    let d = (id % base_start) as usize;
+    id = (id - d as u64) / base_start;
+    name.push(MANGLED_VAR_START[d] as char);
 
-        name
+    while id > 0 {
+        let d = (id % base_digits) as usize;
+        id = (id - d as u64) / base_digits;
+        name.push(MANGLED_VAR_DIGITS[d] as char);
     }
+
+    name
 }
 
 /// Equivalent to `mangled_shuffled.lua` where the character order is randomised.
@@ -1,29 +1,82 @@
 //! Parser that builds an AST from tokens.
 
 use crate::ast::{
-    AstNode, Block, Expression, ParseError, ParseResult, ParseWarning, Statement,
+    binary_priority, AstNode, Block, Expression, ExpressionKind, FunctionBody, ParseError,
+    ParseResult, ParseWarning, Span, Statement, StatementKind, TableField, UNARY_PRIORITY,
 };
-use crate::lexer::{Token, TokenKind, TokenValue};
+use crate::lexer::{tokenize, Token, TokenKind, TokenValue};
 use crate::lua::LuaVersion;
 
 /// Parse a slice of tokens into an [`AstNode`].
 pub fn parse(tokens: &[Token], version: LuaVersion) -> Result<ParseResult, ParseError> {
     let mut parser = Parser::new(tokens, version);
     let block = parser.parse_block()?;
+    if parser.current().kind != TokenKind::Eof {
+        return Err(parser.unexpected("<eof>"));
+    }
     let ast = AstNode::new(block);
     Ok(ParseResult::new(ast, parser.warnings))
 }
 
+/// Parse a slice of tokens, collecting every [`ParseError`] instead of
+/// stopping at the first one.
+///
+/// After an error the parser skips ahead to the next statement boundary: a
+/// keyword that starts a statement, a block terminator such as `end`, or the
+/// first token of a new line that can begin a statement. The returned AST
+/// contains all statements that parsed successfully.
+pub fn parse_recovering(tokens: &[Token], version: LuaVersion) -> ParseResult {
+    let mut parser = Parser::new(tokens, version);
+    parser.recover = true;
+    let mut statements = Vec::new();
+    loop {
+        // A block of the main chunk can only be ended early by a stray
+        // terminator; report it and keep going.
+        match parser.parse_block() {
+            Ok(block) => statements.extend(block.statements),
+            Err(err) => parser.errors.push(err),
+        }
+        if parser.current().kind == TokenKind::Eof {
+            break;
+        }
+        parser.errors.push(parser.unexpected("<eof>"));
+        parser.advance();
+    }
+    let mut result = ParseResult::new(AstNode::new(Block::new(statements)), parser.warnings);
+    result.errors = parser.errors;
+    result
+}
+
+/// Parse Lua source that ships with the crate, such as runtime support code
+/// inserted by steps. That source is known to be valid, so errors panic.
+pub(crate) fn parse_snippet(source: &str) -> Block {
+    let tokens = tokenize(source, LuaVersion::Lua51).expect("snippet must tokenize");
+    parse(&tokens, LuaVersion::Lua51).expect("snippet must parse").ast.block
+}
+
 struct Parser<'a> {
     tokens: &'a [Token],
     index: usize,
     version: LuaVersion,
     warnings: Vec<ParseWarning>,
+    /// Whether each enclosing function accepts `...`; the main chunk does.
+    vararg_scopes: Vec<bool>,
+    /// Whether statement errors are collected and skipped.
+    recover: bool,
+    errors: Vec<ParseError>,
 }
 
 impl<'a> Parser<'a> {
     fn new(tokens: &'a [Token], version: LuaVersion) -> Self {
-        Self { tokens, index: 0, version, warnings: Vec::new() }
+        Self {
+            tokens,
+            index: 0,
+            version,
+            warnings: Vec::new(),
+            vararg_scopes: vec![true],
+            recover: false,
+            errors: Vec::new(),
+        }
     }
 
     fn current(&self) -> &'a Token {
@@ -44,143 +97,549 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn is_symbol(&self, symbol: &str) -> bool {
+        let tok = self.current();
+        tok.kind == TokenKind::Symbol && self.token_string(tok) == Some(symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        let tok = self.current();
+        tok.kind == TokenKind::Keyword && self.token_string(tok) == Some(keyword)
+    }
+
+    fn consume_symbol(&mut self, symbol: &str) -> bool {
+        if self.is_symbol(symbol) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.consume_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{symbol}'")))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.consume_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{keyword}`")))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        let tok = self.current();
+        if tok.kind == TokenKind::Ident {
+            let name = self.token_string(tok).unwrap().to_string();
+            self.advance();
+            Ok(name)
+        } else {
+            Err(self.unexpected("identifier"))
+        }
+    }
+
+    /// Build an error for the current token when `expected` was required.
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let tok = self.current();
+        let found = match tok.kind {
+            TokenKind::Eof => "<eof>".to_string(),
+            _ => format!("`{}`", tok.source),
+        };
+        ParseError::new(format!("expected {expected} near {found}"), tok.line, tok.column)
+    }
+
+    /// Whether the current token closes the enclosing block.
+    fn is_block_end(&self) -> bool {
+        let tok = self.current();
+        match tok.kind {
+            TokenKind::Eof => true,
+            TokenKind::Keyword => matches!(
+                self.token_string(tok),
+                Some("end" | "else" | "elseif" | "until")
+            ),
+            _ => false,
+        }
+    }
+
     fn parse_block(&mut self) -> Result<Block, ParseError> {
         let mut statements = Vec::new();
-        while self.current().kind != TokenKind::Eof {
+        while !self.is_block_end() {
             // Skip semicolons; LuaU warns about them.
-            if self.current().kind == TokenKind::Symbol {
-                if let Some(";") = self.token_string(self.current()) {
-                    if matches!(self.version, LuaVersion::LuaU) {
-                        self.warnings.push(ParseWarning::new(
-                            "Unnecessary semicolon in LuaU",
-                            self.current().line,
-                            self.current().column,
-                        ));
-                    }
-                    self.advance();
-                    continue;
+            if self.is_symbol(";") {
+                if matches!(self.version, LuaVersion::LuaU) {
+                    self.warnings.push(ParseWarning::new(
+                        "Unnecessary semicolon in LuaU",
+                        self.current().line,
+                        self.current().column,
+                    ));
                 }
+                self.advance();
+                continue;
             }
 
-            let stmt = self.parse_statement()?;
+            let start = self.index;
+            let vararg_depth = self.vararg_scopes.len();
+            let stmt = match self.parse_statement() {
+                Ok(stmt) => stmt,
+                Err(err) if self.recover => {
+                    self.errors.push(err);
+                    self.vararg_scopes.truncate(vararg_depth);
+                    self.synchronize(start);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let is_return = matches!(stmt.kind, StatementKind::Return(_));
             statements.push(stmt);
+            if is_return {
+                // `return` must be the last statement of a block.
+                self.consume_symbol(";");
+                if !self.is_block_end() {
+                    let err = self.unexpected("end of block after `return`");
+                    if !self.recover {
+                        return Err(err);
+                    }
+                    self.errors.push(err);
+                    continue;
+                }
+                break;
+            }
         }
         Ok(Block::new(statements))
     }
 
+    /// Skip tokens after a failed statement that began at token `start` until
+    /// a statement boundary is reached.
+    fn synchronize(&mut self, start: usize) {
+        if self.index == start {
+            self.advance();
+        }
+        while self.current().kind != TokenKind::Eof {
+            let tok = self.current();
+            let previous = &self.tokens[self.index - 1];
+            match tok.kind {
+                TokenKind::Keyword
+                    if matches!(
+                        self.token_string(tok),
+                        Some(
+                            "local" | "function" | "if" | "while" | "for" | "repeat" | "do"
+                                | "return" | "break" | "end" | "else" | "elseif" | "until"
+                        )
+                    ) =>
+                {
+                    return;
+                }
+                TokenKind::Ident if tok.line > previous.line => return,
+                TokenKind::Symbol if tok.line > previous.line && self.is_symbol("(") => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Span from the token at index `start` to the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        let first = &self.tokens[start];
+        let last = &self.tokens[self.index.saturating_sub(1).max(start)];
+        Span::new(first.start, last.end, first.line, first.column)
+    }
+
+    /// Build an expression spanning from the token at index `start`.
+    fn node(&self, kind: ExpressionKind, start: usize) -> Expression {
+        Expression::new(kind, self.span_from(start))
+    }
+
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
-        if self.current().kind == TokenKind::Keyword {
-            if let Some(kw) = self.token_string(self.current()) {
-                match kw {
-                    "local" => {
-                        self.advance();
-                        return self.parse_local_assignment();
-                    }
-                    "return" => {
-                        self.advance();
-                        if self.current().kind == TokenKind::Eof {
-                            return Ok(Statement::Return(None));
-                        }
-                        // Attempt to parse expression; on error return error at expression start.
-                        let expr = self.parse_expression()?;
-                        return Ok(Statement::Return(Some(expr)));
+        let start = self.index;
+        let kind = self.parse_statement_kind()?;
+        Ok(Statement::new(kind, self.span_from(start)))
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<StatementKind, ParseError> {
+        if self.current().kind == TokenKind::Keyword
+            && let Some(kw) = self.token_string(self.current())
+        {
+            match kw {
+                "local" => {
+                    self.advance();
+                    if self.consume_keyword("function") {
+                        return self.parse_local_function();
                     }
-                    "break" => {
-                        self.advance();
-                        return Ok(Statement::Break);
+                    return self.parse_local_assignment();
+                }
+                "function" => {
+                    self.advance();
+                    return self.parse_function_declaration();
+                }
+                "do" => {
+                    self.advance();
+                    let block = self.parse_block()?;
+                    self.expect_keyword("end")?;
+                    return Ok(StatementKind::Do(block));
+                }
+                "while" => {
+                    self.advance();
+                    let condition = self.parse_expression()?;
+                    self.expect_keyword("do")?;
+                    let block = self.parse_block()?;
+                    self.expect_keyword("end")?;
+                    return Ok(StatementKind::While { condition, block });
+                }
+                "repeat" => {
+                    self.advance();
+                    let block = self.parse_block()?;
+                    self.expect_keyword("until")?;
+                    let condition = self.parse_expression()?;
+                    return Ok(StatementKind::Repeat { block, condition });
+                }
+                "if" => {
+                    self.advance();
+                    return self.parse_if();
+                }
+                "for" => {
+                    self.advance();
+                    return self.parse_for();
+                }
+                "return" => {
+                    self.advance();
+                    if self.is_block_end() || self.is_symbol(";") {
+                        return Ok(StatementKind::Return(Vec::new()));
                     }
-                    "continue" => {
-                        let tok = self.current().clone();
-                        self.advance();
-                        if matches!(self.version, LuaVersion::Lua51) {
-                            return Err(ParseError::new(
-                                "`continue` is not supported in Lua 5.1",
-                                tok.line,
-                                tok.column,
-                            ));
-                        } else {
-                            return Ok(Statement::Continue);
-                        }
+                    let exprs = self.parse_expression_list()?;
+                    return Ok(StatementKind::Return(exprs));
+                }
+                "break" => {
+                    self.advance();
+                    return Ok(StatementKind::Break);
+                }
+                "continue" => {
+                    let tok = self.current().clone();
+                    self.advance();
+                    if matches!(self.version, LuaVersion::Lua51) {
+                        return Err(ParseError::new(
+                            "`continue` is not supported in Lua 5.1",
+                            tok.line,
+                            tok.column,
+                        ));
+                    } else {
+                        return Ok(StatementKind::Continue);
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
 
-        // Fallback: assignment or expression statement
-        self.parse_assignment_or_expr()
+        // Fallback: assignment or call statement
+        self.parse_assignment_or_call()
     }
 
-    fn parse_local_assignment(&mut self) -> Result<Statement, ParseError> {
+    fn parse_local_assignment(&mut self) -> Result<StatementKind, ParseError> {
         let tok = self.current().clone();
         if tok.kind != TokenKind::Ident {
             return Err(ParseError::new("expected identifier after `local`", tok.line, tok.column));
         }
-        let name = self.token_string(&tok).unwrap().to_string();
-        self.advance();
+        let mut names = vec![self.expect_ident()?];
+        while self.consume_symbol(",") {
+            names.push(self.expect_ident()?);
+        }
+
+        let exprs = if self.consume_symbol("=") {
+            self.parse_expression_list()?
+        } else {
+            Vec::new()
+        };
+        Ok(StatementKind::LocalAssignment { names, exprs })
+    }
 
-        let eq_tok = self.current().clone();
-        if !(eq_tok.kind == TokenKind::Symbol && self.token_string(&eq_tok) == Some("=")) {
-            return Err(ParseError::new("expected '=' in local assignment", eq_tok.line, eq_tok.column));
+    fn parse_local_function(&mut self) -> Result<StatementKind, ParseError> {
+        let name = self.expect_ident()?;
+        let body = self.parse_function_body()?;
+        Ok(StatementKind::LocalFunction { name, body })
+    }
+
+    fn parse_function_declaration(&mut self) -> Result<StatementKind, ParseError> {
+        let start = self.index;
+        let name = self.expect_ident()?;
+        let mut target = self.node(ExpressionKind::Variable(name), start);
+        while self.consume_symbol(".") {
+            let key = self.parse_field_name()?;
+            target = self.node(
+                ExpressionKind::Index { object: Box::new(target), key: Box::new(key) },
+                start,
+            );
+        }
+        let method = if self.consume_symbol(":") {
+            Some(self.expect_ident()?)
+        } else {
+            None
+        };
+        let body = self.parse_function_body()?;
+        Ok(StatementKind::FunctionDeclaration { target, method, body })
+    }
+
+    /// Parse `(params) block end` following the `function` keyword and name.
+    fn parse_function_body(&mut self) -> Result<FunctionBody, ParseError> {
+        self.expect_symbol("(")?;
+        let mut params = Vec::new();
+        let mut is_vararg = false;
+        if !self.is_symbol(")") {
+            loop {
+                if self.consume_symbol("...") {
+                    is_vararg = true;
+                    break;
+                }
+                params.push(self.expect_ident()?);
+                if !self.consume_symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(")")?;
+        self.vararg_scopes.push(is_vararg);
+        let block = self.parse_block();
+        self.vararg_scopes.pop();
+        let block = block?;
+        self.expect_keyword("end")?;
+        Ok(FunctionBody::new(params, is_vararg, block))
+    }
+
+    fn parse_if(&mut self) -> Result<StatementKind, ParseError> {
+        let mut clauses = Vec::new();
+        loop {
+            let condition = self.parse_expression()?;
+            self.expect_keyword("then")?;
+            let block = self.parse_block()?;
+            clauses.push((condition, block));
+            if !self.consume_keyword("elseif") {
+                break;
+            }
+        }
+        let else_block = if self.consume_keyword("else") {
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+        self.expect_keyword("end")?;
+        Ok(StatementKind::If { clauses, else_block })
+    }
+
+    fn parse_for(&mut self) -> Result<StatementKind, ParseError> {
+        let first = self.expect_ident()?;
+        if self.consume_symbol("=") {
+            let start = self.parse_expression()?;
+            self.expect_symbol(",")?;
+            let limit = self.parse_expression()?;
+            let step = if self.consume_symbol(",") {
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
+            self.expect_keyword("do")?;
+            let block = self.parse_block()?;
+            self.expect_keyword("end")?;
+            return Ok(StatementKind::NumericFor { var: first, start, limit, step, block });
+        }
+
+        let mut names = vec![first];
+        while self.consume_symbol(",") {
+            names.push(self.expect_ident()?);
+        }
+        if !self.consume_keyword("in") {
+            return Err(self.unexpected("'=' or `in`"));
         }
-        self.advance();
-        let expr = self.parse_expression()?;
-        Ok(Statement::LocalAssignment { name, expr })
+        let exprs = self.parse_expression_list()?;
+        self.expect_keyword("do")?;
+        let block = self.parse_block()?;
+        self.expect_keyword("end")?;
+        Ok(StatementKind::GenericFor { names, exprs, block })
     }
 
-    fn parse_assignment_or_expr(&mut self) -> Result<Statement, ParseError> {
+    fn parse_assignment_or_call(&mut self) -> Result<StatementKind, ParseError> {
         let tok = self.current().clone();
-        if tok.kind == TokenKind::Ident {
-            let name = self.token_string(&tok).unwrap().to_string();
-            self.advance();
-            if self.current().kind == TokenKind::Symbol
-                && self.token_string(self.current()) == Some("=")
+        let expr = self.parse_suffixed_expression()?;
+        if self.is_symbol("=") || self.is_symbol(",") {
+            let mut targets = vec![expr];
+            while self.consume_symbol(",") {
+                targets.push(self.parse_suffixed_expression()?);
+            }
+            if targets.iter().any(|t| !t.is_assignable()) {
+                return Err(ParseError::new(
+                    "cannot assign to this expression",
+                    tok.line,
+                    tok.column,
+                ));
+            }
+            self.expect_symbol("=")?;
+            let exprs = self.parse_expression_list()?;
+            return Ok(StatementKind::Assignment { targets, exprs });
+        }
+
+        if expr.is_call() {
+            Ok(StatementKind::Expression(expr))
+        } else {
+            Err(self.unexpected("'=' or function call"))
+        }
+    }
+
+    fn parse_expression_list(&mut self) -> Result<Vec<Expression>, ParseError> {
+        let mut exprs = vec![self.parse_expression()?];
+        while self.consume_symbol(",") {
+            exprs.push(self.parse_expression()?);
+        }
+        Ok(exprs)
+    }
+
+    /// Parse a name or parenthesized expression followed by any number of
+    /// `.name`, `[expr]`, `:method(args)` and call suffixes.
+    fn parse_suffixed_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.index;
+        let kind = if self.current().kind == TokenKind::Ident {
+            ExpressionKind::Variable(self.expect_ident()?)
+        } else if self.consume_symbol("(") {
+            let inner = self.parse_expression()?;
+            self.expect_symbol(")")?;
+            ExpressionKind::Paren(Box::new(inner))
+        } else {
+            return Err(self.unexpected("expression"));
+        };
+        let mut expr = self.node(kind, start);
+
+        loop {
+            let kind = if self.consume_symbol(".") {
+                let key = self.parse_field_name()?;
+                ExpressionKind::Index { object: Box::new(expr), key: Box::new(key) }
+            } else if self.consume_symbol("[") {
+                let key = self.parse_expression()?;
+                self.expect_symbol("]")?;
+                ExpressionKind::Index { object: Box::new(expr), key: Box::new(key) }
+            } else if self.consume_symbol(":") {
+                let method = self.expect_ident()?;
+                let args = self.parse_call_arguments()?;
+                ExpressionKind::MethodCall { object: Box::new(expr), method, args }
+            } else if self.is_symbol("(")
+                || self.is_symbol("{")
+                || self.current().kind == TokenKind::String
             {
-                self.advance();
-                let expr = self.parse_expression()?;
-                return Ok(Statement::Assignment { name, expr });
+                let args = self.parse_call_arguments()?;
+                ExpressionKind::Call { func: Box::new(expr), args }
             } else {
-                let expr = Expression::Variable(name);
-                return Ok(Statement::Expression(expr));
-            }
+                return Ok(expr);
+            };
+            expr = self.node(kind, start);
         }
+    }
+
+    /// Parse the name after `.` as a string key.
+    fn parse_field_name(&mut self) -> Result<Expression, ParseError> {
+        let start = self.index;
+        let name = self.expect_ident()?;
+        Ok(self.node(ExpressionKind::String(name), start))
+    }
 
-        // Otherwise treat as expression statement
-        let expr = self.parse_expression()?;
-        Ok(Statement::Expression(expr))
+    /// Parse `(args)` or a single string or table argument.
+    fn parse_call_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        if self.is_symbol("{") {
+            return Ok(vec![self.parse_table()?]);
+        }
+        if self.current().kind == TokenKind::String
+            && let TokenValue::String(s) = &self.current().value
+        {
+            let start = self.index;
+            self.advance();
+            let arg = self.node(ExpressionKind::String(s.clone()), start);
+            return Ok(vec![arg]);
+        }
+        self.expect_symbol("(")?;
+        if self.consume_symbol(")") {
+            return Ok(Vec::new());
+        }
+        let args = self.parse_expression_list()?;
+        self.expect_symbol(")")?;
+        Ok(args)
     }
 
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
-        self.parse_binary_expression()
+        self.parse_binary_expression(0)
     }
 
-    fn parse_binary_expression(&mut self) -> Result<Expression, ParseError> {
-        let mut left = self.parse_primary()?;
-        loop {
-            if self.current().kind == TokenKind::Symbol {
-                if let Some(op @ "+") | Some(op @ "-") = self.token_string(self.current()) {
-                    self.advance();
-                    let right = self.parse_primary()?;
-                    left = Expression::BinaryOp {
-                        left: Box::new(left),
-                        op: op.to_string(),
-                        right: Box::new(right),
-                    };
-                    continue;
-                }
+    /// Precedence climbing: parse operands and fold every binary operator whose
+    /// left priority is greater than `limit`.
+    fn parse_binary_expression(&mut self, limit: u8) -> Result<Expression, ParseError> {
+        let start = self.index;
+        let mut left = match self.unary_operator() {
+            Some(op) => {
+                self.advance();
+                let operand = self.parse_binary_expression(UNARY_PRIORITY)?;
+                self.node(
+                    ExpressionKind::UnaryOp { op: op.to_string(), operand: Box::new(operand) },
+                    start,
+                )
             }
-            break;
+            None => self.parse_simple_expression()?,
+        };
+
+        while let Some(op) = self.binary_operator() {
+            let (left_priority, right_priority) = binary_priority(op).unwrap();
+            if left_priority <= limit {
+                break;
+            }
+            self.advance();
+            let right = self.parse_binary_expression(right_priority)?;
+            let span = left.span.to(right.span);
+            left = Expression::new(
+                ExpressionKind::BinaryOp {
+                    left: Box::new(left),
+                    op: op.to_string(),
+                    right: Box::new(right),
+                },
+                span,
+            );
         }
         Ok(left)
     }
 
-    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+    fn unary_operator(&self) -> Option<&'a str> {
+        let tok = self.current();
+        match (&tok.kind, self.token_string(tok)) {
+            (TokenKind::Keyword, Some("not")) | (TokenKind::Symbol, Some("-" | "#")) => {
+                self.token_string(tok)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary_operator(&self) -> Option<&'a str> {
+        let tok = self.current();
+        match tok.kind {
+            TokenKind::Keyword | TokenKind::Symbol => self
+                .token_string(tok)
+                .filter(|op| binary_priority(op).is_some()),
+            _ => None,
+        }
+    }
+
+    fn parse_simple_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.index;
         let tok = self.current().clone();
         match tok.kind {
             TokenKind::Number => {
                 if let TokenValue::Number(n) = tok.value {
                     self.advance();
-                    Ok(Expression::Number(n))
+                    Ok(self.node(ExpressionKind::Number(n), start))
                 } else {
                     unreachable!()
                 }
@@ -188,35 +647,52 @@ impl<'a> Parser<'a> {
             TokenKind::String => {
                 if let TokenValue::String(s) = tok.value.clone() {
                     self.advance();
-                    Ok(Expression::String(s))
+                    Ok(self.node(ExpressionKind::String(s), start))
                 } else {
                     unreachable!()
                 }
             }
-            TokenKind::Ident => {
-                let name = self.token_string(&tok).unwrap().to_string();
-                self.advance();
-                Ok(Expression::Variable(name))
-            }
-            TokenKind::Symbol => {
-                if self.token_string(&tok) == Some("(") {
+            TokenKind::Keyword => match self.token_string(&tok) {
+                Some("nil") => {
                     self.advance();
-                    let expr = self.parse_expression()?;
-                    let close = self.current().clone();
-                    if close.kind == TokenKind::Symbol && self.token_string(&close) == Some(")") {
-                        self.advance();
-                        Ok(expr)
-                    } else {
-                        Err(ParseError::new("expected ')'", close.line, close.column))
+                    Ok(self.node(ExpressionKind::Nil, start))
+                }
+                Some("true") => {
+                    self.advance();
+                    Ok(self.node(ExpressionKind::Boolean(true), start))
+                }
+                Some("false") => {
+                    self.advance();
+                    Ok(self.node(ExpressionKind::Boolean(false), start))
+                }
+                Some("function") => {
+                    self.advance();
+                    let body = self.parse_function_body()?;
+                    Ok(self.node(ExpressionKind::Function(body), start))
+                }
+                _ => Err(self.unexpected("expression")),
+            },
+            TokenKind::Symbol => match self.token_string(&tok) {
+                Some("...") => {
+                    if !self.vararg_scopes.last().copied().unwrap_or(false) {
+                        return Err(ParseError::new(
+                            "cannot use '...' outside a vararg function",
+                            tok.line,
+                            tok.column,
+                        ));
                     }
-                } else {
-                    Err(ParseError::new(
-                        format!("unexpected symbol `{}`", self.token_string(&tok).unwrap_or("")),
-                        tok.line,
-                        tok.column,
-                    ))
+                    self.advance();
+                    Ok(self.node(ExpressionKind::Vararg, start))
                 }
-            }
+                Some("{") => self.parse_table(),
+                Some("(") => self.parse_suffixed_expression(),
+                _ => Err(ParseError::new(
+                    format!("unexpected symbol `{}`", self.token_string(&tok).unwrap_or("")),
+                    tok.line,
+                    tok.column,
+                )),
+            },
+            TokenKind::Ident => self.parse_suffixed_expression(),
             _ => Err(ParseError::new(
                 "unexpected token in expression",
                 tok.line,
@@ -224,6 +700,42 @@ impl<'a> Parser<'a> {
             )),
         }
     }
+
+    /// Parse a table constructor starting at `{`.
+    fn parse_table(&mut self) -> Result<Expression, ParseError> {
+        let start = self.index;
+        self.expect_symbol("{")?;
+        let mut fields = Vec::new();
+        while !self.is_symbol("}") {
+            if self.consume_symbol("[") {
+                let key = self.parse_expression()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol("=")?;
+                let value = self.parse_expression()?;
+                fields.push(TableField::Keyed { key, value });
+            } else if self.current().kind == TokenKind::Ident
+                && self.peek_is_symbol(1, "=")
+            {
+                let name = self.expect_ident()?;
+                self.advance();
+                let value = self.parse_expression()?;
+                fields.push(TableField::Named { name, value });
+            } else {
+                fields.push(TableField::Value(self.parse_expression()?));
+            }
+            if !self.consume_symbol(",") && !self.consume_symbol(";") {
+                break;
+            }
+        }
+        self.expect_symbol("}")?;
+        Ok(self.node(ExpressionKind::Table(fields), start))
+    }
+
+    fn peek_is_symbol(&self, offset: usize, symbol: &str) -> bool {
+        self.tokens.get(self.index + offset).is_some_and(|tok| {
+            tok.kind == TokenKind::Symbol && self.token_string(tok) == Some(symbol)
+        })
+    }
 }
 
 #[cfg(test)]
@@ -231,38 +743,244 @@ mod tests {
     use super::*;
     use crate::lexer::tokenize;
 
+    fn parse_str(src: &str) -> Vec<StatementKind> {
+        let tokens = tokenize(src, LuaVersion::Lua51).unwrap();
+        let statements = parse(&tokens, LuaVersion::Lua51).unwrap().ast.block.statements;
+        statements.into_iter().map(|stmt| stmt.kind).collect()
+    }
+
     #[test]
     fn parse_local_assignment() {
-        let tokens = tokenize("local a = 1", LuaVersion::Lua51);
+        let tokens = tokenize("local a = 1", LuaVersion::Lua51).unwrap();
         let result = parse(&tokens, LuaVersion::Lua51).unwrap();
         assert!(result.warnings.is_empty());
         assert_eq!(
             result.ast.block.statements,
-            vec![Statement::LocalAssignment { name: "a".into(), expr: Expression::Number(1.0) }]
+            vec![Statement::from(StatementKind::LocalAssignment {
+                names: vec!["a".into()],
+                exprs: vec![ExpressionKind::Number(1.0).into()],
+            })]
         );
     }
 
     #[test]
     fn parse_continue_luau() {
-        let tokens = tokenize("continue", LuaVersion::LuaU);
+        let tokens = tokenize("continue", LuaVersion::LuaU).unwrap();
         let result = parse(&tokens, LuaVersion::LuaU).unwrap();
         assert!(result.warnings.is_empty());
-        assert_eq!(result.ast.block.statements, vec![Statement::Continue]);
+        assert_eq!(result.ast.block.statements, vec![StatementKind::Continue.into()]);
     }
 
     #[test]
     fn parse_continue_lua51_error() {
         // Tokenize using LuaU so `continue` becomes a keyword, then parse as Lua51.
-        let tokens = tokenize("continue", LuaVersion::LuaU);
+        let tokens = tokenize("continue", LuaVersion::LuaU).unwrap();
         let err = parse(&tokens, LuaVersion::Lua51).unwrap_err();
         assert!(err.message.contains("continue"));
     }
 
     #[test]
     fn semicolon_warning_in_luau() {
-        let tokens = tokenize("a = 1;", LuaVersion::LuaU);
+        let tokens = tokenize("a = 1;", LuaVersion::LuaU).unwrap();
         let result = parse(&tokens, LuaVersion::LuaU).unwrap();
         assert_eq!(result.warnings.len(), 1);
     }
-}
 
+    #[test]
+    fn parse_multiple_assignment_targets() {
+        let stmts = parse_str("a, b.c, d[1] = 1, 2, 3");
+        let StatementKind::Assignment { targets, exprs } = &stmts[0] else {
+            panic!("expected assignment, got {:?}", stmts[0]);
+        };
+        assert_eq!(targets.len(), 3);
+        assert_eq!(exprs.len(), 3);
+        assert_eq!(
+            targets[1],
+            ExpressionKind::Index {
+                object: Box::new(var("b")),
+                key: Box::new(ExpressionKind::String("c".into()).into()),
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn parse_function_declarations() {
+        let stmts = parse_str(
+            "local function f(a, ...) return a end function t.x:m() end",
+        );
+        assert!(matches!(
+            &stmts[0],
+            StatementKind::LocalFunction { name, body }
+                if name == "f" && body.params == ["a"] && body.is_vararg
+        ));
+        assert!(matches!(
+            &stmts[1],
+            StatementKind::FunctionDeclaration { method: Some(m), .. } if m == "m"
+        ));
+    }
+
+    #[test]
+    fn parse_control_flow() {
+        let stmts = parse_str(
+            "if a then b() elseif c then d() else e() end \
+             while a do break end \
+             repeat a() until b \
+             for i = 1, 10, 2 do end \
+             for k, v in pairs(t) do end \
+             do local x end",
+        );
+        assert!(matches!(&stmts[0], StatementKind::If { clauses, else_block: Some(_) } if clauses.len() == 2));
+        assert!(matches!(&stmts[1], StatementKind::While { .. }));
+        assert!(matches!(&stmts[2], StatementKind::Repeat { .. }));
+        assert!(matches!(&stmts[3], StatementKind::NumericFor { step: Some(_), .. }));
+        assert!(matches!(&stmts[4], StatementKind::GenericFor { names, .. } if names.len() == 2));
+        assert!(matches!(&stmts[5], StatementKind::Do(_)));
+    }
+
+    #[test]
+    fn return_must_end_block() {
+        let tokens = tokenize("return 1 print(2)", LuaVersion::Lua51).unwrap();
+        assert!(parse(&tokens, LuaVersion::Lua51).is_err());
+    }
+
+    #[test]
+    fn bare_expression_statement_is_error() {
+        let tokens = tokenize("a", LuaVersion::Lua51).unwrap();
+        assert!(parse(&tokens, LuaVersion::Lua51).is_err());
+    }
+
+    fn parse_expr(src: &str) -> Expression {
+        match parse_str(&format!("return {src}")).remove(0) {
+            StatementKind::Return(mut exprs) => exprs.remove(0),
+            other => panic!("expected return, got {other:?}"),
+        }
+    }
+
+    fn bin(left: Expression, op: &str, right: Expression) -> Expression {
+        ExpressionKind::BinaryOp { left: Box::new(left), op: op.into(), right: Box::new(right) }.into()
+    }
+
+    fn unary(op: &str, operand: Expression) -> Expression {
+        ExpressionKind::UnaryOp { op: op.into(), operand: Box::new(operand) }.into()
+    }
+
+    fn var(name: &str) -> Expression {
+        ExpressionKind::Variable(name.into()).into()
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let neg_d = unary("-", var("d"));
+        assert_eq!(
+            parse_expr("a + b * c ^ -d .. e"),
+            bin(
+                bin(var("a"), "+", bin(var("b"), "*", bin(var("c"), "^", neg_d))),
+                "..",
+                var("e"),
+            )
+        );
+    }
+
+    #[test]
+    fn right_associative_operators() {
+        assert_eq!(
+            parse_expr("a .. b .. c"),
+            bin(var("a"), "..", bin(var("b"), "..", var("c")))
+        );
+        assert_eq!(
+            parse_expr("a ^ b ^ c"),
+            bin(var("a"), "^", bin(var("b"), "^", var("c")))
+        );
+        assert_eq!(
+            parse_expr("a - b - c"),
+            bin(bin(var("a"), "-", var("b")), "-", var("c"))
+        );
+    }
+
+    #[test]
+    fn unary_binds_looser_than_pow() {
+        // -a ^ 2 is -(a ^ 2), not (-a) ^ 2
+        assert_eq!(
+            parse_expr("-a ^ 2"),
+            unary("-", bin(var("a"), "^", ExpressionKind::Number(2.0).into()))
+        );
+        assert_eq!(
+            parse_expr("not a == b"),
+            bin(unary("not", var("a")), "==", var("b"))
+        );
+    }
+
+    #[test]
+    fn table_and_function_literals() {
+        let ExpressionKind::Table(fields) = parse_expr("{ 1, x = 2, [3] = 4; function(...) return ... end }").kind
+        else {
+            panic!("expected table");
+        };
+        assert_eq!(fields.len(), 4);
+        assert!(matches!(fields[1], TableField::Named { .. }));
+        assert!(matches!(fields[2], TableField::Keyed { .. }));
+        assert!(matches!(&fields[3], TableField::Value(Expression { kind: ExpressionKind::Function(body), .. })
+            if body.is_vararg));
+    }
+
+    #[test]
+    fn call_sugar_and_literals() {
+        assert!(matches!(parse_expr("f{1}").kind, ExpressionKind::Call { args, .. } if args.len() == 1));
+        assert!(matches!(parse_expr("o:m'x'").kind, ExpressionKind::MethodCall { .. }));
+        assert_eq!(parse_expr("nil").kind, ExpressionKind::Nil);
+        assert_eq!(parse_expr("true").kind, ExpressionKind::Boolean(true));
+        assert!(matches!(parse_expr("(f())").kind, ExpressionKind::Paren(_)));
+    }
+
+    #[test]
+    fn vararg_outside_vararg_function_is_error() {
+        let tokens = tokenize("local function f() return ... end", LuaVersion::Lua51).unwrap();
+        assert!(parse(&tokens, LuaVersion::Lua51).is_err());
+    }
+
+    #[test]
+    fn recovering_collects_all_errors() {
+        let src = "local a = = 1\nprint(a)\nif x then y = end\nlocal function f() return ... end\nz = 2\nend";
+        let tokens = tokenize(src, LuaVersion::Lua51).unwrap();
+        let result = parse_recovering(&tokens, LuaVersion::Lua51);
+        let lines: Vec<usize> = result.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 3, 4, 6]);
+        assert!(!result.is_ok());
+        // Statements around the errors are kept.
+        let statements = &result.ast.block.statements;
+        assert!(matches!(&statements[0].kind, StatementKind::Expression(e) if e.is_call()));
+        assert!(matches!(statements[1].kind, StatementKind::If { .. }));
+        assert!(matches!(statements.last().unwrap().kind, StatementKind::Assignment { .. }));
+    }
+
+    #[test]
+    fn recovering_matches_strict_parse_on_valid_input() {
+        let tokens = tokenize("a = 1; b = 2", LuaVersion::LuaU).unwrap();
+        let result = parse_recovering(&tokens, LuaVersion::LuaU);
+        assert!(result.is_ok());
+        assert_eq!(result, parse(&tokens, LuaVersion::LuaU).unwrap());
+    }
+
+    #[test]
+    fn nodes_carry_spans() {
+        let src = "local x = 1\nprint(x + f(2))";
+        let tokens = tokenize(src, LuaVersion::Lua51).unwrap();
+        let statements = parse(&tokens, LuaVersion::Lua51).unwrap().ast.block.statements;
+        assert_eq!(statements[0].span, Span::new(0, 11, 1, 1));
+        assert_eq!(statements[1].span, Span::new(12, 27, 2, 1));
+        let StatementKind::Expression(call) = &statements[1].kind else {
+            panic!("expected call");
+        };
+        let ExpressionKind::Call { args, .. } = &call.kind else {
+            panic!("expected call");
+        };
+        let sum = &args[0];
+        assert_eq!(&src[sum.span.start..sum.span.end], "x + f(2)");
+        assert_eq!((sum.span.line, sum.span.column), (2, 7));
+        let ExpressionKind::BinaryOp { right, .. } = &sum.kind else {
+            panic!("expected binary operation");
+        };
+        assert_eq!(&src[right.span.start..right.span.end], "f(2)");
+    }
+}
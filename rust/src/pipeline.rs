@@ -1,14 +1,27 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde_json::Value;
+
 use crate::config::Config;
+use crate::error::PrometheusError;
 use crate::lexer::tokenize;
 use crate::lua::LuaVersion;
 use crate::name_generators::{
     ConfuseGenerator, IlGenerator, MangledGenerator, MangledShuffledGenerator, NumberGenerator,
 };
 use crate::parser::parse;
-use crate::step::{Step, StepConstructor};
+use crate::renamer::rename_variables;
+use crate::schema;
+use crate::scope::{self, ScopeInfo};
+use crate::step::{validate_settings, Step, StepConstructor};
 use crate::steps;
+use crate::unparser::unparse;
+
+/// Names accepted by [`Pipeline::set_name_generator`].
+pub const NAME_GENERATORS: [&str; 5] = ["Mangled", "MangledShuffled", "Il", "Confuse", "Number"];
 
 /// Trait for variable name generators.
 pub trait NameGenerator {
@@ -24,7 +37,10 @@ pub struct Pipeline {
     pub seed: u64,
     pub name_generator: Box<dyn NameGenerator>,
     steps: Vec<Box<dyn Step>>,
+    /// Steps queued by other steps while the pipeline is applied.
+    deferred: RefCell<Vec<Box<dyn Step>>>,
     step_constructors: HashMap<String, StepConstructor>,
+    scope: ScopeInfo,
 }
 
 impl Pipeline {
@@ -42,7 +58,9 @@ impl Pipeline {
             seed,
             name_generator: Box::new(MangledShuffledGenerator::new(seed)),
             steps: Vec::new(),
+            deferred: RefCell::new(Vec::new()),
             step_constructors: HashMap::new(),
+            scope: ScopeInfo::default(),
         };
         // Register built-in steps so they can be referenced from configuration.
         steps::register_builtin_steps(&mut pipeline);
@@ -55,7 +73,7 @@ impl Pipeline {
     }
 
     /// Set the name generator by predefined name.
-    pub fn set_name_generator(&mut self, name: &str) -> Result<(), String> {
+    pub fn set_name_generator(&mut self, name: &str) -> Result<(), PrometheusError> {
         let seed = self.seed;
         self.name_generator = match name {
             "Mangled" => Box::new(MangledGenerator::new()),
@@ -63,13 +81,13 @@ impl Pipeline {
             "Il" => Box::new(IlGenerator::new(seed)),
             "Confuse" => Box::new(ConfuseGenerator::new(seed)),
             "Number" => Box::new(NumberGenerator::new()),
-            _ => return Err(format!("unknown name generator {name}")),
+            _ => return Err(PrometheusError::UnknownNameGenerator(name.to_string())),
         };
         Ok(())
     }
 
     /// Construct a pipeline from a [`Config`].
-    pub fn from_config(config: Config) -> Result<Self, String> {
+    pub fn from_config(config: Config) -> Result<Self, PrometheusError> {
         let mut pipeline = Pipeline::new(
             config.lua_version,
             config.pretty_print,
@@ -82,37 +100,88 @@ impl Pipeline {
             let constructor = pipeline
                 .step_constructors
                 .get(&step_cfg.name)
-                .ok_or_else(|| format!("step {} not registered", step_cfg.name))?;
+                .ok_or_else(|| PrometheusError::UnknownStep(step_cfg.name.clone()))?;
             let step = constructor(&step_cfg.settings);
+            validate_settings(&step_cfg.name, step.settings_descriptor(), &step_cfg.settings)?;
             pipeline.steps.push(step);
         }
 
         Ok(pipeline)
     }
 
+    /// JSON Schema for configurations that use the steps registered with
+    /// this pipeline.
+    pub fn config_schema(&self) -> Value {
+        let mut steps: Vec<(&str, Box<dyn Step>)> = self
+            .step_constructors
+            .iter()
+            .map(|(name, constructor)| (name.as_str(), constructor(&HashMap::new())))
+            .collect();
+        steps.sort_by_key(|(name, _)| *name);
+        schema::config_schema(&steps)
+    }
+
+    /// Scope information for the AST handed to the step that is currently
+    /// being applied. Steps that modify the AST can recompute it with
+    /// [`scope::analyze`].
+    pub fn scope(&self) -> &ScopeInfo {
+        &self.scope
+    }
+
+    /// Random number generator derived from the configured seed. Steps pass
+    /// their own `salt` so that they do not all draw the same numbers.
+    pub fn rng(&self, salt: &str) -> StdRng {
+        // FNV-1a keeps the derived seed stable across Rust versions.
+        let hash = salt.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        StdRng::seed_from_u64(self.seed ^ hash)
+    }
+
     /// Manually add a step instance to the pipeline.
     pub fn add_step(&mut self, step: Box<dyn Step>) {
         self.steps.push(step);
     }
 
+    /// Queue `step` to run after all configured steps, for steps whose work
+    /// must not be transformed by the steps that follow them.
+    pub fn defer_step(&self, step: Box<dyn Step>) {
+        self.deferred.borrow_mut().push(step);
+    }
+
     /// Apply the pipeline to the given Lua source code.
-    ///
-    /// Currently this parses the code and applies steps, but returns the input
-    /// unchanged until an unparser is available.
-    pub fn apply(&mut self, code: &str) -> Result<String, String> {
-        let tokens = tokenize(code, self.lua_version);
-        let parse_result = parse(&tokens, self.lua_version).map_err(|e| format!("{:?}", e))?;
-        let mut ast = parse_result.ast;
+    pub fn apply(&mut self, code: &str) -> Result<String, PrometheusError> {
+        let tokens = tokenize(code, self.lua_version)?;
+        let parse_result = parse(&tokens, self.lua_version)?;
+        let ast = parse_result.ast;
 
+        self.deferred.get_mut().clear();
         let mut steps = std::mem::take(&mut self.steps);
-        for step in steps.iter_mut() {
-            ast = step.apply(ast, self);
-        }
+        let result = steps.iter_mut().try_fold(ast, |ast, step| {
+            self.scope = scope::analyze(&ast);
+            step.apply(ast, self)
+        });
         self.steps = steps;
+        let mut ast = result?;
+        // Deferred steps may defer further steps themselves
+        loop {
+            let deferred = std::mem::take(self.deferred.get_mut());
+            if deferred.is_empty() {
+                break;
+            }
+            for mut step in deferred {
+                self.scope = scope::analyze(&ast);
+                ast = step.apply(ast, self)?;
+            }
+        }
 
-        // TODO: integrate variable renaming and unparsing when implemented.
-        let _ = ast; // silence unused variable warning until unparser exists
-        Ok(code.to_string())
+        rename_variables(
+            &mut ast,
+            self.name_generator.as_mut(),
+            &self.var_name_prefix,
+            self.lua_version,
+        );
+        Ok(unparse(&ast, self.lua_version, self.pretty_print))
     }
 }
 
@@ -135,4 +204,52 @@ mod tests {
         let out = pipeline.apply(src).unwrap();
         assert_eq!(out, src);
     }
+
+    #[test]
+    fn apply_pretty_prints() {
+        let config = Config { pretty_print: true, ..Config::default() };
+        let mut pipeline = Pipeline::from_config(config).unwrap();
+        let out = pipeline.apply("if a then print(a) end").unwrap();
+        assert_eq!(out, "if a then\n\tprint(a)\nend\n");
+    }
+
+    #[test]
+    fn apply_renames_locals_with_prefix() {
+        let config = Config {
+            name_generator: "Number".to_string(),
+            var_name_prefix: "p".to_string(),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::from_config(config).unwrap();
+        let out = pipeline.apply("local value = 1 print(value)").unwrap();
+        assert_eq!(out, "local p_1=1 print(p_1)");
+    }
+
+    #[test]
+    fn errors_are_typed() {
+        let config = Config { name_generator: "Nope".to_string(), ..Config::default() };
+        assert!(matches!(
+            Pipeline::from_config(config),
+            Err(PrometheusError::UnknownNameGenerator(name)) if name == "Nope"
+        ));
+
+        let mut config = Config::default();
+        config.steps.push(crate::config::Step { name: "Nope".into(), settings: HashMap::new() });
+        assert!(matches!(Pipeline::from_config(config), Err(PrometheusError::UnknownStep(_))));
+
+        let mut config = Config::default();
+        let settings = HashMap::from([("Iteration".to_string(), serde_json::json!(2))]);
+        config.steps.push(crate::config::Step { name: "WrapInFunction".into(), settings });
+        assert!(matches!(
+            Pipeline::from_config(config),
+            Err(PrometheusError::InvalidSetting { step, setting, .. })
+                if step == "WrapInFunction" && setting == "Iteration"
+        ));
+
+        let mut pipeline = Pipeline::from_config(Config::default()).unwrap();
+        match pipeline.apply("local = 1") {
+            Err(PrometheusError::Parse(err)) => assert_eq!(err.line, 1),
+            other => panic!("expected parse error, got {other:?}"),
+        }
+    }
 }
@@ -1,32 +1,30 @@
 use rand::Rng;
 
-use crate::ast::Expression;
-use crate::pipeline::Pipeline;
+use crate::ast::{Expression, ExpressionKind};
+use crate::name_generators::mangled_name;
 
 use crate::random_strings;
 
-/// Create a random string literal using the pipeline's name generator.
-pub fn string_literal(pipeline: &mut Pipeline) -> Expression {
-    Expression::String(pipeline.name_generator.generate())
+/// Create a random string literal that looks like a generated variable name.
+pub fn string_literal(rng: &mut impl Rng) -> Expression {
+    ExpressionKind::String(mangled_name(rng.gen_range(1..=4096))).into()
 }
 
 /// Create a random dictionary key represented as a string expression.
-pub fn dictionary_literal() -> Expression {
-    random_strings::random_string_expr(None)
+pub fn dictionary_literal(rng: &mut impl Rng) -> Expression {
+    ExpressionKind::String(random_strings::random_string_with(rng, None)).into()
 }
 
 /// Create a random number literal in the range used by the Lua codebase.
-pub fn number_literal() -> Expression {
-    let mut rng = rand::thread_rng();
-    Expression::Number(rng.gen_range(-8_388_608..=8_388_607) as f64)
+pub fn number_literal(rng: &mut impl Rng) -> Expression {
+    ExpressionKind::Number(rng.gen_range(-8_388_608..=8_388_607) as f64).into()
 }
 
 /// Return a random literal of any of the supported types.
-pub fn any_literal(pipeline: &mut Pipeline) -> Expression {
-    let mut rng = rand::thread_rng();
+pub fn any_literal(rng: &mut impl Rng) -> Expression {
     match rng.gen_range(1..=3) {
-        1 => string_literal(pipeline),
-        2 => number_literal(),
-        _ => dictionary_literal(),
+        1 => string_literal(rng),
+        2 => number_literal(rng),
+        _ => dictionary_literal(rng),
     }
 }
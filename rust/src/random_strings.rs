@@ -1,7 +1,7 @@
 use rand::Rng;
 use rand::seq::SliceRandom;
 
-use crate::ast::Expression;
+use crate::ast::{Expression, ExpressionKind};
 
 const CHARSET: &[u8] = b"qwertyuiopasdfghjklzxcvbnmQWERTYUIOPASDFGHJKLZXCVBNM1234567890";
 
@@ -9,18 +9,22 @@ const CHARSET: &[u8] = b"qwertyuiopasdfghjklzxcvbnmQWERTYUIOPASDFGHJKLZXCVBNM123
 /// list is returned, otherwise a string of random characters with a random
 /// length between 2 and 15 is produced.
 pub fn random_string(words: Option<&[&str]>) -> String {
-    let mut rng = rand::thread_rng();
+    random_string_with(&mut rand::thread_rng(), words)
+}
+
+/// Like [`random_string`], but draws from `rng`.
+pub fn random_string_with(rng: &mut impl Rng, words: Option<&[&str]>) -> String {
     if let Some(words) = words {
-        words.choose(&mut rng).unwrap().to_string()
+        words.choose(rng).unwrap().to_string()
     } else {
         let len = rng.gen_range(2..=15);
         (0..len)
-            .map(|_| *CHARSET.choose(&mut rng).unwrap() as char)
+            .map(|_| *CHARSET.choose(rng).unwrap() as char)
             .collect()
     }
 }
 
 /// Convenience wrapper returning the string as an [`Expression`].
 pub fn random_string_expr(words: Option<&[&str]>) -> Expression {
-    Expression::String(random_string(words))
+    ExpressionKind::String(random_string(words)).into()
 }
@@ -0,0 +1,161 @@
+//! Final pass giving every local variable a generated name.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::ast::{AstNode, Expression, ExpressionKind};
+use crate::lua::LuaVersion;
+use crate::pipeline::NameGenerator;
+use crate::scope::{self, BindingId, BindingKind, Resolution, ScopeInfo, ScopeVisitor};
+
+/// Rename all local bindings of `ast` using names from `generator`.
+///
+/// Names are handed out in generation order and reused whenever two bindings
+/// can never be observed from the same place, so the shortest names are used
+/// as often as possible. Generated names are prefixed with `prefix` and never
+/// equal a keyword of `version` or a global referenced by the script.
+pub fn rename_variables(
+    ast: &mut AstNode,
+    generator: &mut dyn NameGenerator,
+    prefix: &str,
+    version: LuaVersion,
+) {
+    let info = scope::analyze(ast);
+    let mut pool = NamePool {
+        generator,
+        prefix,
+        keywords: version.conventions().keywords,
+        globals: &info.globals,
+        names: Vec::new(),
+    };
+    let names = assign_names(&info, &mut pool);
+    scope::resolve(ast, &mut Rename { names });
+}
+
+/// Lazily generated list of candidate names.
+struct NamePool<'a> {
+    generator: &'a mut dyn NameGenerator,
+    prefix: &'a str,
+    keywords: &'static [&'static str],
+    globals: &'a BTreeSet<String>,
+    names: Vec<String>,
+}
+
+impl NamePool<'_> {
+    fn get(&mut self, index: usize) -> &str {
+        while self.names.len() <= index {
+            let name = format!("{}{}", self.prefix, self.generator.generate());
+            if !self.keywords.contains(&name.as_str()) && !self.globals.contains(&name) {
+                self.names.push(name);
+            }
+        }
+        &self.names[index]
+    }
+}
+
+/// Pick a name for every binding. Scopes are numbered in pre-order, so the
+/// names of all enclosing scopes are known when a scope is processed.
+fn assign_names(info: &ScopeInfo, pool: &mut NamePool) -> HashMap<BindingId, String> {
+    let mut names: HashMap<BindingId, String> = HashMap::new();
+    for scope in &info.scopes {
+        // A binding must not shadow an outer binding that is still used
+        // inside this scope, nor reuse the name of a sibling binding.
+        let mut taken: BTreeSet<String> = scope
+            .outer_references
+            .iter()
+            .map(|id| names[id].clone())
+            .collect();
+        for &id in &scope.bindings {
+            let binding = info.binding(id);
+            let name = if binding.kind == BindingKind::SelfParameter {
+                binding.name.clone()
+            } else {
+                (0..)
+                    .map(|i| pool.get(i).to_string())
+                    .find(|name| !taken.contains(name))
+                    .unwrap()
+            };
+            taken.insert(name.clone());
+            names.insert(id, name);
+        }
+    }
+    names
+}
+
+struct Rename {
+    names: HashMap<BindingId, String>,
+}
+
+impl ScopeVisitor for Rename {
+    fn declaration(&mut self, id: BindingId, name: &mut String) {
+        *name = self.names[&id].clone();
+    }
+
+    fn reference(&mut self, resolution: Resolution, expr: &mut Expression) {
+        if let (Resolution::Local(id), ExpressionKind::Variable(name)) = (resolution, &mut expr.kind) {
+            *name = self.names[&id].clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::name_generators::{MangledGenerator, NumberGenerator};
+    use crate::parser::parse;
+    use crate::unparser::unparse;
+
+    fn rename(src: &str, generator: &mut dyn NameGenerator, prefix: &str) -> String {
+        let mut ast = parse(&tokenize(src, LuaVersion::Lua51).unwrap(), LuaVersion::Lua51).unwrap().ast;
+        rename_variables(&mut ast, generator, prefix, LuaVersion::Lua51);
+        unparse(&ast, LuaVersion::Lua51, false)
+    }
+
+    #[test]
+    fn renames_locals_and_keeps_globals() {
+        let out = rename(
+            "local value = 1 local function add(x) return x + value end print(add(2))",
+            &mut NumberGenerator::new(),
+            "",
+        );
+        assert_eq!(out, "local _1=1 local function _2(_2)return _2+_1 end print(_2(2))");
+    }
+
+    #[test]
+    fn reuses_names_in_disjoint_scopes() {
+        let out = rename(
+            "local function f(a) return a end local function g(b) return b end",
+            &mut NumberGenerator::new(),
+            "",
+        );
+        assert_eq!(out, "local function _1(_1)return _1 end local function _2(_1)return _1 end");
+    }
+
+    #[test]
+    fn avoids_shadowing_used_outer_names() {
+        let out = rename(
+            "local a = 1 do local b = 2 print(a + b) end",
+            &mut NumberGenerator::new(),
+            "",
+        );
+        assert_eq!(out, "local _1=1 do local _2=2 print(_1+_2)end");
+    }
+
+    #[test]
+    fn skips_keywords_and_globals() {
+        // The mangled generator produces `b`, `c`, ..., `do`, ...; `c` is a
+        // global read by the script and must not be used.
+        let out = rename("local x = c local y = x", &mut MangledGenerator::new(), "");
+        assert_eq!(out, "local b=c local d=b");
+    }
+
+    #[test]
+    fn applies_prefix_and_keeps_self() {
+        let out = rename(
+            "function t:m(a) return self, a end",
+            &mut NumberGenerator::new(),
+            "v",
+        );
+        assert_eq!(out, "function t:m(v_1)return self,v_1 end");
+    }
+}
@@ -0,0 +1,153 @@
+//! JSON Schema for the configuration format read by [`Config::from_json`].
+//!
+//! The schema of every step is generated from its [`SettingDescriptor`]s, so
+//! editors flag the same unknown keys and settings, wrong kinds, out of range
+//! numbers and invalid choices that [`Pipeline::from_config`] rejects.
+//!
+//! [`Config::from_json`]: crate::config::Config::from_json
+//! [`Pipeline::from_config`]: crate::pipeline::Pipeline::from_config
+
+use serde_json::{json, Map, Value};
+
+use crate::config::Config;
+use crate::lua::LuaVersion;
+use crate::pipeline::NAME_GENERATORS;
+use crate::step::{DefaultValue, SettingDescriptor, SettingKind, Step};
+
+/// Schema for configurations using the `steps`, given with the names they
+/// are registered under.
+pub fn config_schema(steps: &[(&str, Box<dyn Step>)]) -> Value {
+    let defaults = Config::default();
+    let versions: Vec<&str> = LuaVersion::ALL.iter().map(LuaVersion::name).collect();
+    let steps: Vec<Value> = steps.iter().map(|(name, step)| step_schema(name, step.as_ref())).collect();
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Prometheus configuration",
+        "type": "object",
+        "properties": {
+            "LuaVersion": {
+                "description": "The Lua version of the input and output",
+                "enum": versions,
+                "default": defaults.lua_version.name(),
+            },
+            "VarNamePrefix": {
+                "description": "Prefix of the renamed variables",
+                "type": "string",
+                "default": defaults.var_name_prefix,
+            },
+            "NameGenerator": {
+                "description": "Generator of the names of renamed variables",
+                "enum": NAME_GENERATORS,
+                "default": defaults.name_generator,
+            },
+            "PrettyPrint": {
+                "description": "Whether to pretty print the output",
+                "type": "boolean",
+                "default": defaults.pretty_print,
+            },
+            "Seed": {
+                "description": "Seed of the random choices made by the steps",
+                "type": "integer",
+                "minimum": 0,
+                "default": defaults.seed,
+            },
+            "Steps": {
+                "description": "The steps to apply, in order",
+                "type": "array",
+                "items": { "oneOf": steps },
+            },
+        },
+        "additionalProperties": false,
+    })
+}
+
+fn step_schema(name: &str, step: &dyn Step) -> Value {
+    let settings: Map<String, Value> = step
+        .settings_descriptor()
+        .iter()
+        .map(|descriptor| (descriptor.name.to_string(), setting_schema(descriptor)))
+        .collect();
+    json!({
+        "description": step.description(),
+        "type": "object",
+        "properties": {
+            "Name": { "const": name },
+            "Settings": {
+                "type": "object",
+                "properties": settings,
+                "additionalProperties": false,
+            },
+        },
+        "required": ["Name"],
+        "additionalProperties": false,
+    })
+}
+
+fn setting_schema(descriptor: &SettingDescriptor) -> Value {
+    let mut schema = Map::new();
+    schema.insert("description".into(), descriptor.description.into());
+    match descriptor.kind {
+        SettingKind::Boolean => {
+            schema.insert("type".into(), "boolean".into());
+        }
+        SettingKind::Number | SettingKind::Integer => {
+            let kind = if matches!(descriptor.kind, SettingKind::Integer) { "integer" } else { "number" };
+            schema.insert("type".into(), kind.into());
+            if let Some(min) = descriptor.min {
+                schema.insert("minimum".into(), number(min));
+            }
+            if let Some(max) = descriptor.max {
+                schema.insert("maximum".into(), number(max));
+            }
+        }
+        SettingKind::String => {
+            schema.insert("type".into(), "string".into());
+        }
+        SettingKind::Enum => {
+            schema.insert("type".into(), "string".into());
+            schema.insert("enum".into(), descriptor.values.into());
+        }
+    }
+    let default = match descriptor.default {
+        DefaultValue::Bool(value) => value.into(),
+        DefaultValue::Number(value) => number(value),
+        DefaultValue::Str(value) => value.into(),
+    };
+    schema.insert("default".into(), default);
+    Value::Object(schema)
+}
+
+/// Whole numbers are written without a fractional part.
+fn number(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < 2f64.powi(53) {
+        json!(value as i64)
+    } else {
+        json!(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::Pipeline;
+    use crate::config::Config;
+
+    #[test]
+    fn schema_describes_registered_steps() {
+        let schema = Pipeline::from_config(Config::default()).unwrap().config_schema();
+        assert_eq!(schema["additionalProperties"], false);
+        let steps = schema["properties"]["Steps"]["items"]["oneOf"].as_array().unwrap();
+        let names: Vec<&str> =
+            steps.iter().map(|step| step["properties"]["Name"]["const"].as_str().unwrap()).collect();
+        assert!(names.contains(&"Vmify") && names.contains(&"WatermarkCheck"), "{names:?}");
+
+        let constant_array = &steps[names.iter().position(|name| *name == "ConstantArray").unwrap()];
+        let settings = &constant_array["properties"]["Settings"];
+        assert_eq!(settings["additionalProperties"], false);
+        let treshold = &settings["properties"]["Treshold"];
+        assert_eq!((&treshold["minimum"], &treshold["maximum"]), (&0.into(), &1.into()));
+        assert_eq!(treshold["type"], "number");
+        assert_eq!(settings["properties"]["LocalWrapperCount"]["type"], "integer");
+        assert_eq!(settings["properties"]["Encoding"]["enum"], serde_json::json!(["none", "base64"]));
+        assert_eq!(settings["properties"]["Encoding"]["default"], "base64");
+    }
+}
@@ -0,0 +1,495 @@
+//! Scope analysis resolving every variable to the local it refers to.
+//!
+//! Each local declaration (locals, local functions, parameters and loop
+//! variables) gets a unique [`BindingId`]. Every [`ExpressionKind::Variable`],
+//! including assignment targets, is resolved either to such a binding or to a
+//! global name.
+
+use std::collections::BTreeSet;
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement, StatementKind, TableField,
+};
+
+/// Identifier of a local binding, unique within one [`ScopeInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BindingId(pub usize);
+
+/// Identifier of a lexical scope, unique within one [`ScopeInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScopeId(pub usize);
+
+/// How a binding was introduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    /// `local a`
+    Local,
+    /// `local function a() end`
+    LocalFunction,
+    /// Function parameter.
+    Parameter,
+    /// The implicit `self` parameter of `function a:b() end`. It has no
+    /// declaration site in the source and therefore cannot be renamed.
+    SelfParameter,
+    /// Variable of a numeric or generic `for` loop.
+    ForVariable,
+}
+
+/// A single local variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    /// Name used in the source.
+    pub name: String,
+    pub kind: BindingKind,
+    /// Scope the binding was declared in.
+    pub scope: ScopeId,
+    /// Function the binding belongs to; `0` is the main chunk.
+    pub function: usize,
+    /// Number of reads and writes of the binding.
+    pub references: usize,
+    /// Whether the binding is assigned to after its declaration.
+    pub assigned: bool,
+    /// Whether the binding is referenced from a nested function as an upvalue.
+    pub captured: bool,
+}
+
+/// A lexical scope: a block, function body or loop body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    /// Function the scope belongs to; `0` is the main chunk.
+    pub function: usize,
+    /// Bindings declared directly in this scope, in declaration order.
+    pub bindings: Vec<BindingId>,
+    /// Bindings of enclosing scopes referenced in this scope or any nested one.
+    pub outer_references: BTreeSet<BindingId>,
+    /// Globals referenced in this scope or any nested one.
+    pub globals: BTreeSet<String>,
+}
+
+/// What a variable refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Local(BindingId),
+    Global,
+}
+
+/// Result of the scope analysis.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScopeInfo {
+    /// All scopes; the main chunk scope is [`ScopeInfo::ROOT`].
+    pub scopes: Vec<Scope>,
+    pub bindings: Vec<Binding>,
+    /// Every global name referenced by the script.
+    pub globals: BTreeSet<String>,
+}
+
+impl ScopeInfo {
+    /// Scope of the main chunk.
+    pub const ROOT: ScopeId = ScopeId(0);
+
+    pub fn binding(&self, id: BindingId) -> &Binding {
+        &self.bindings[id.0]
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0]
+    }
+}
+
+/// Callbacks invoked while the AST is resolved. Both receive mutable access so
+/// that passes can rename declarations or replace variable references.
+pub trait ScopeVisitor {
+    /// Called for every declared name, right after it comes into scope.
+    fn declaration(&mut self, _id: BindingId, _name: &mut String) {}
+    /// Called for every [`ExpressionKind::Variable`]. `expr` may be replaced; the
+    /// replacement is not visited.
+    fn reference(&mut self, _resolution: Resolution, _expr: &mut Expression) {}
+    /// Called instead of [`ScopeVisitor::reference`] for a variable that is
+    /// assigned to, including the name of `function a() end`.
+    fn assignment(&mut self, resolution: Resolution, expr: &mut Expression) {
+        self.reference(resolution, expr);
+    }
+}
+
+struct NoopVisitor;
+
+impl ScopeVisitor for NoopVisitor {}
+
+/// Analyze the scopes of `ast` without modifying it.
+pub fn analyze(ast: &AstNode) -> ScopeInfo {
+    // The resolver works on a mutable tree; run it over a scratch copy.
+    resolve(&mut ast.clone(), &mut NoopVisitor)
+}
+
+/// Resolve all variables of `ast`, invoking `visitor` for every declaration
+/// and reference in source order.
+pub fn resolve(ast: &mut AstNode, visitor: &mut impl ScopeVisitor) -> ScopeInfo {
+    let mut resolver = Resolver {
+        visitor,
+        info: ScopeInfo::default(),
+        current: None,
+        function: 0,
+        function_count: 1,
+        visible: Vec::new(),
+    };
+    resolver.block(&mut ast.block);
+    resolver.info
+}
+
+struct Resolver<'v, V: ScopeVisitor> {
+    visitor: &'v mut V,
+    info: ScopeInfo,
+    current: Option<ScopeId>,
+    function: usize,
+    function_count: usize,
+    /// Bindings currently in scope with their source names, innermost last.
+    visible: Vec<(String, BindingId)>,
+}
+
+impl<V: ScopeVisitor> Resolver<'_, V> {
+    fn enter_scope(&mut self) -> usize {
+        let id = ScopeId(self.info.scopes.len());
+        self.info.scopes.push(Scope {
+            parent: self.current,
+            function: self.function,
+            bindings: Vec::new(),
+            outer_references: BTreeSet::new(),
+            globals: BTreeSet::new(),
+        });
+        self.current = Some(id);
+        self.visible.len()
+    }
+
+    fn exit_scope(&mut self, mark: usize) {
+        self.visible.truncate(mark);
+        self.current = self.info.scopes[self.current.unwrap().0].parent;
+    }
+
+    fn declare(&mut self, name: &mut String, kind: BindingKind) {
+        let scope = self.current.unwrap();
+        let id = BindingId(self.info.bindings.len());
+        self.info.bindings.push(Binding {
+            name: name.clone(),
+            kind,
+            scope,
+            function: self.function,
+            references: 0,
+            assigned: false,
+            captured: false,
+        });
+        self.info.scopes[scope.0].bindings.push(id);
+        self.visible.push((name.clone(), id));
+        if kind != BindingKind::SelfParameter {
+            self.visitor.declaration(id, name);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<BindingId> {
+        self.visible.iter().rev().find(|(n, _)| n == name).map(|&(_, id)| id)
+    }
+
+    fn variable(&mut self, expr: &mut Expression, write: bool) {
+        let ExpressionKind::Variable(name) = &expr.kind else {
+            unreachable!("variable() called on {expr:?}");
+        };
+        let resolution = match self.lookup(name) {
+            Some(id) => {
+                let function = self.function;
+                let binding = &mut self.info.bindings[id.0];
+                binding.references += 1;
+                binding.assigned |= write;
+                binding.captured |= binding.function != function;
+                let declared_in = binding.scope;
+                let mut scope = self.current;
+                while let Some(s) = scope.filter(|&s| s != declared_in) {
+                    self.info.scopes[s.0].outer_references.insert(id);
+                    scope = self.info.scopes[s.0].parent;
+                }
+                Resolution::Local(id)
+            }
+            None => {
+                let mut scope = self.current;
+                while let Some(s) = scope {
+                    self.info.scopes[s.0].globals.insert(name.clone());
+                    scope = self.info.scopes[s.0].parent;
+                }
+                self.info.globals.insert(name.clone());
+                Resolution::Global
+            }
+        };
+        if write {
+            self.visitor.assignment(resolution, expr);
+        } else {
+            self.visitor.reference(resolution, expr);
+        }
+    }
+
+    fn block(&mut self, block: &mut Block) {
+        let mark = self.enter_scope();
+        self.statements(block);
+        self.exit_scope(mark);
+    }
+
+    fn statements(&mut self, block: &mut Block) {
+        for stmt in block.statements.iter_mut() {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match &mut stmt.kind {
+            StatementKind::LocalAssignment { names, exprs } => {
+                self.expressions(exprs);
+                for name in names.iter_mut() {
+                    self.declare(name, BindingKind::Local);
+                }
+            }
+            StatementKind::LocalFunction { name, body } => {
+                self.declare(name, BindingKind::LocalFunction);
+                self.function_body(body, false);
+            }
+            StatementKind::FunctionDeclaration { target, method, body } => {
+                self.target(target);
+                self.function_body(body, method.is_some());
+            }
+            StatementKind::Assignment { targets, exprs } => {
+                for target in targets.iter_mut() {
+                    self.target(target);
+                }
+                self.expressions(exprs);
+            }
+            StatementKind::Do(block) => self.block(block),
+            StatementKind::While { condition, block } => {
+                self.expression(condition);
+                self.block(block);
+            }
+            StatementKind::Repeat { block, condition } => {
+                // The condition can see the locals of the loop body.
+                let mark = self.enter_scope();
+                self.statements(block);
+                self.expression(condition);
+                self.exit_scope(mark);
+            }
+            StatementKind::If { clauses, else_block } => {
+                for (condition, block) in clauses.iter_mut() {
+                    self.expression(condition);
+                    self.block(block);
+                }
+                if let Some(block) = else_block {
+                    self.block(block);
+                }
+            }
+            StatementKind::NumericFor { var, start, limit, step, block } => {
+                self.expression(start);
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                let mark = self.enter_scope();
+                self.declare(var, BindingKind::ForVariable);
+                self.statements(block);
+                self.exit_scope(mark);
+            }
+            StatementKind::GenericFor { names, exprs, block } => {
+                self.expressions(exprs);
+                let mark = self.enter_scope();
+                for name in names.iter_mut() {
+                    self.declare(name, BindingKind::ForVariable);
+                }
+                self.statements(block);
+                self.exit_scope(mark);
+            }
+            StatementKind::Return(exprs) => self.expressions(exprs),
+            StatementKind::Break | StatementKind::Continue => {}
+            StatementKind::Expression(expr) => self.expression(expr),
+        }
+    }
+
+    /// Visit an assignment target.
+    fn target(&mut self, target: &mut Expression) {
+        if let ExpressionKind::Variable(_) = target.kind {
+            self.variable(target, true);
+        } else {
+            self.expression(target);
+        }
+    }
+
+    fn function_body(&mut self, body: &mut FunctionBody, has_self: bool) {
+        let outer_function = self.function;
+        self.function = self.function_count;
+        self.function_count += 1;
+        let mark = self.enter_scope();
+        if has_self {
+            self.declare(&mut "self".to_string(), BindingKind::SelfParameter);
+        }
+        for param in body.params.iter_mut() {
+            self.declare(param, BindingKind::Parameter);
+        }
+        self.statements(&mut body.block);
+        self.exit_scope(mark);
+        self.function = outer_function;
+    }
+
+    fn expressions(&mut self, exprs: &mut [Expression]) {
+        for expr in exprs.iter_mut() {
+            self.expression(expr);
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        if let ExpressionKind::Variable(_) = expr.kind {
+            return self.variable(expr, false);
+        }
+        match &mut expr.kind {
+            ExpressionKind::Variable(_)
+            | ExpressionKind::Nil
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::Number(_)
+            | ExpressionKind::String(_)
+            | ExpressionKind::Vararg => {}
+            ExpressionKind::Function(body) => self.function_body(body, false),
+            ExpressionKind::Table(fields) => {
+                for field in fields.iter_mut() {
+                    match field {
+                        TableField::Value(value) | TableField::Named { value, .. } => {
+                            self.expression(value)
+                        }
+                        TableField::Keyed { key, value } => {
+                            self.expression(key);
+                            self.expression(value);
+                        }
+                    }
+                }
+            }
+            ExpressionKind::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionKind::UnaryOp { operand, .. } => self.expression(operand),
+            ExpressionKind::Paren(inner) => self.expression(inner),
+            ExpressionKind::Index { object, key } => {
+                self.expression(object);
+                self.expression(key);
+            }
+            ExpressionKind::Call { func, args } => {
+                self.expression(func);
+                self.expressions(args);
+            }
+            ExpressionKind::MethodCall { object, args, .. } => {
+                self.expression(object);
+                self.expressions(args);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::lua::LuaVersion;
+    use crate::parser::parse;
+
+    fn ast(src: &str) -> AstNode {
+        parse(&tokenize(src, LuaVersion::Lua51).unwrap(), LuaVersion::Lua51).unwrap().ast
+    }
+
+    /// Collects the resolution of every reference in order.
+    #[derive(Default)]
+    struct Collect(Vec<(String, Resolution)>);
+
+    impl ScopeVisitor for Collect {
+        fn reference(&mut self, resolution: Resolution, expr: &mut Expression) {
+            if let ExpressionKind::Variable(name) = &expr.kind {
+                self.0.push((name.clone(), resolution));
+            }
+        }
+    }
+
+    fn references(src: &str) -> (ScopeInfo, Vec<(String, Resolution)>) {
+        let mut collect = Collect::default();
+        let info = resolve(&mut ast(src), &mut collect);
+        (info, collect.0)
+    }
+
+    #[test]
+    fn shadowing_creates_new_bindings() {
+        let (info, refs) = references("local a = 1 local a = a + 1 print(a)");
+        assert_eq!(info.bindings.len(), 2);
+        // `a + 1` sees the first binding, `print(a)` the second.
+        assert_eq!(refs[0], ("a".into(), Resolution::Local(BindingId(0))));
+        assert_eq!(refs[1], ("print".into(), Resolution::Global));
+        assert_eq!(refs[2], ("a".into(), Resolution::Local(BindingId(1))));
+    }
+
+    #[test]
+    fn tracks_upvalues_and_assignments() {
+        let (info, _) = references(
+            "local x = 1 local y = 2 local function f() x = x + 1 return y end f()",
+        );
+        let x = info.binding(BindingId(0));
+        assert!(x.captured && x.assigned);
+        assert_eq!(x.references, 2);
+        let y = info.binding(BindingId(1));
+        assert!(y.captured && !y.assigned);
+        let f = info.binding(BindingId(2));
+        assert_eq!(f.kind, BindingKind::LocalFunction);
+        assert!(!f.captured);
+    }
+
+    #[test]
+    fn local_function_is_visible_in_its_body() {
+        let (_, refs) = references("local function f() return f() end");
+        assert_eq!(refs, vec![("f".into(), Resolution::Local(BindingId(0)))]);
+        let (_, refs) = references("local f = function() return f() end");
+        assert_eq!(refs, vec![("f".into(), Resolution::Global)]);
+    }
+
+    #[test]
+    fn loop_scoping() {
+        let (info, refs) = references(
+            "for i = 1, i do print(i) end repeat local done = true until done",
+        );
+        assert_eq!(refs[0], ("i".into(), Resolution::Global));
+        assert_eq!(refs[2], ("i".into(), Resolution::Local(BindingId(0))));
+        assert_eq!(refs[3], ("done".into(), Resolution::Local(BindingId(1))));
+        assert_eq!(info.binding(BindingId(0)).kind, BindingKind::ForVariable);
+    }
+
+    #[test]
+    fn method_declarations_bind_self() {
+        let (info, refs) = references("function t:m() return self end");
+        assert_eq!(refs[0], ("t".into(), Resolution::Global));
+        assert_eq!(refs[1], ("self".into(), Resolution::Local(BindingId(0))));
+        assert_eq!(info.binding(BindingId(0)).kind, BindingKind::SelfParameter);
+    }
+
+    #[test]
+    fn scopes_record_outer_references_and_globals() {
+        let info = analyze(&ast("local a do local b = a print(b) end"));
+        let inner = info.scope(info.binding(BindingId(1)).scope);
+        assert!(inner.outer_references.contains(&BindingId(0)));
+        assert!(inner.globals.contains("print"));
+        assert!(info.scope(ScopeInfo::ROOT).outer_references.is_empty());
+        assert_eq!(info.globals, BTreeSet::from(["print".to_string()]));
+    }
+
+    #[test]
+    fn visitor_can_rename_declarations() {
+        struct Rename;
+        impl ScopeVisitor for Rename {
+            fn declaration(&mut self, id: BindingId, name: &mut String) {
+                *name = format!("v{}", id.0);
+            }
+            fn reference(&mut self, resolution: Resolution, expr: &mut Expression) {
+                if let (Resolution::Local(id), ExpressionKind::Variable(name)) = (resolution, &mut expr.kind) {
+                    *name = format!("v{}", id.0);
+                }
+            }
+        }
+        let mut tree = ast("local a = 1 local function f(a) return a end print(f(a))");
+        resolve(&mut tree, &mut Rename);
+        assert_eq!(tree, ast("local v0 = 1 local function v1(v2) return v2 end print(v1(v0))"));
+    }
+}
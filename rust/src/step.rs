@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde_json::Value;
 
 use crate::ast::AstNode;
+use crate::error::PrometheusError;
 use crate::pipeline::Pipeline;
 
 /// Supported types for a [`SettingDescriptor`].
@@ -10,6 +11,8 @@ use crate::pipeline::Pipeline;
 pub enum SettingKind {
     Boolean,
     Number,
+    /// A number without fractional part.
+    Integer,
     String,
     Enum,
 }
@@ -72,6 +75,17 @@ impl SettingDescriptor {
         }
     }
 
+    /// Convenience constructor for whole-number settings with optional bounds.
+    pub const fn integer(
+        name: &'static str,
+        description: &'static str,
+        default: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> Self {
+        Self { kind: SettingKind::Integer, ..Self::number(name, description, default, min, max) }
+    }
+
     /// Convenience constructor for string settings.
     pub const fn string(
         name: &'static str,
@@ -117,9 +131,150 @@ pub trait Step {
     /// Descriptor of supported settings.
     fn settings_descriptor(&self) -> &'static [SettingDescriptor];
     /// Apply the transformation to the AST.
-    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> AstNode;
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError>;
 }
 
 /// Factory type used for constructing steps from configuration.
 pub type StepConstructor = fn(&HashMap<String, Value>) -> Box<dyn Step>;
 
+/// Check the `settings` configured for the step called `step` against its
+/// `descriptors`. Steps fall back to the default of settings they cannot
+/// read, so every key must be declared and every value must have the
+/// declared kind and lie within the declared bounds or choices.
+pub fn validate_settings(
+    step: &str,
+    descriptors: &[SettingDescriptor],
+    settings: &HashMap<String, Value>,
+) -> Result<(), PrometheusError> {
+    // Sorted so that the first error does not depend on the hash order
+    let mut names: Vec<&String> = settings.keys().collect();
+    names.sort();
+    for name in names {
+        let invalid = |message: String| PrometheusError::InvalidSetting {
+            step: step.to_string(),
+            setting: name.clone(),
+            message,
+        };
+        let Some(descriptor) = descriptors.iter().find(|d| d.name == name) else {
+            let expected = if descriptors.is_empty() {
+                "the step has no settings".to_string()
+            } else {
+                let names: Vec<&str> = descriptors.iter().map(|d| d.name).collect();
+                format!("expected one of {}", names.join(", "))
+            };
+            return Err(invalid(format!("unknown setting, {expected}")));
+        };
+        check_value(descriptor, &settings[name]).map_err(invalid)?;
+    }
+    Ok(())
+}
+
+fn check_value(descriptor: &SettingDescriptor, value: &Value) -> Result<(), String> {
+    let expected = match descriptor.kind {
+        SettingKind::Boolean => "a boolean",
+        SettingKind::Number => "a number",
+        SettingKind::Integer => "a whole number",
+        SettingKind::String | SettingKind::Enum => "a string",
+    };
+    let wrong_kind = || format!("expected {expected}, found {}", kind_of(value));
+    match descriptor.kind {
+        SettingKind::Boolean => {
+            value.as_bool().ok_or_else(wrong_kind)?;
+        }
+        SettingKind::String => {
+            value.as_str().ok_or_else(wrong_kind)?;
+        }
+        SettingKind::Number | SettingKind::Integer => {
+            let number = value.as_f64().ok_or_else(wrong_kind)?;
+            if matches!(descriptor.kind, SettingKind::Integer) && number.fract() != 0.0 {
+                return Err(format!("expected {expected}, found {value}"));
+            }
+            let (min, max) = (descriptor.min, descriptor.max);
+            if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                let range = match (min, max) {
+                    (Some(min), Some(max)) => format!("between {min} and {max}"),
+                    (Some(min), None) => format!("at least {min}"),
+                    (None, Some(max)) => format!("at most {max}"),
+                    (None, None) => unreachable!(),
+                };
+                return Err(format!("{value} is out of range, expected {range}"));
+            }
+        }
+        SettingKind::Enum => {
+            let choice = value.as_str().ok_or_else(wrong_kind)?;
+            if !descriptor.values.contains(&choice) {
+                return Err(format!(
+                    "{value} is not a valid choice, expected one of {}",
+                    descriptor.values.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn kind_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SETTINGS: [SettingDescriptor; 4] = [
+        SettingDescriptor::boolean("Flag", "", true),
+        SettingDescriptor::number("Amount", "", 0.5, Some(0.0), Some(1.0)),
+        SettingDescriptor::integer("Count", "", 1.0, Some(1.0), None),
+        SettingDescriptor::enumeration("Mode", "", "a", &["a", "b"]),
+    ];
+
+    fn validate(name: &str, value: Value) -> Result<(), String> {
+        let settings = HashMap::from([(name.to_string(), value)]);
+        validate_settings("Test", &SETTINGS, &settings).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn settings_are_checked_against_descriptors() {
+        assert_eq!(validate("Flag", json!(false)), Ok(()));
+        assert_eq!(validate("Amount", json!(1)), Ok(()));
+        assert_eq!(validate("Mode", json!("b")), Ok(()));
+        assert_eq!(
+            validate("Amout", json!(1)).unwrap_err(),
+            "invalid setting Amout of step Test: unknown setting, expected one of Flag, Amount, Count, Mode"
+        );
+        assert_eq!(
+            validate("Flag", json!("true")).unwrap_err(),
+            "invalid setting Flag of step Test: expected a boolean, found a string"
+        );
+        assert_eq!(
+            validate("Amount", json!(1.5)).unwrap_err(),
+            "invalid setting Amount of step Test: 1.5 is out of range, expected between 0 and 1"
+        );
+        assert_eq!(validate("Count", json!(2)), Ok(()));
+        assert_eq!(validate("Count", json!(2.0)), Ok(()));
+        assert_eq!(
+            validate("Count", json!(2.5)).unwrap_err(),
+            "invalid setting Count of step Test: expected a whole number, found 2.5"
+        );
+        assert_eq!(
+            validate("Count", json!(0)).unwrap_err(),
+            "invalid setting Count of step Test: 0 is out of range, expected at least 1"
+        );
+        assert_eq!(
+            validate("Mode", json!("c")).unwrap_err(),
+            "invalid setting Mode of step Test: \"c\" is not a valid choice, expected one of a, b"
+        );
+        let settings = HashMap::from([("Flag".to_string(), json!(true))]);
+        let err = validate_settings("Test", &[], &settings).unwrap_err();
+        assert!(err.to_string().ends_with("the step has no settings"), "{err}");
+    }
+}
+
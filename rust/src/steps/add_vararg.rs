@@ -0,0 +1,94 @@
+//! Transformation behind the [`AddVararg`] step.
+//!
+//! Every function literal that is not vararg gets a trailing `...`. In Lua
+//! 5.1 a vararg function declares an implicit local `arg` holding its extra
+//! arguments, so functions that mention `arg`, directly or in a nested
+//! function, are left alone.
+
+use crate::ast::{AstNode, Expression, ExpressionKind, FunctionBody, Statement, StatementKind};
+use crate::lua::LuaVersion;
+use crate::pipeline::Pipeline;
+use crate::visitor::{visit_ast, walk_expression, walk_function_body, walk_statement, VisitorMut};
+
+pub(super) fn apply(mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let mut adder = Adder {
+        compat_arg: matches!(pipeline.lua_version, LuaVersion::Lua51),
+        uses_arg: Vec::new(),
+    };
+    visit_ast(&mut adder, &mut ast);
+    ast
+}
+
+struct Adder {
+    /// Whether vararg functions declare the implicit `arg` local.
+    compat_arg: bool,
+    /// For every enclosing function, whether it mentions `arg`.
+    uses_arg: Vec<bool>,
+}
+
+impl VisitorMut for Adder {
+    fn visit_function_body(&mut self, body: &mut FunctionBody) {
+        self.uses_arg.push(self.compat_arg && body.params.iter().any(|param| param == "arg"));
+        walk_function_body(self, body);
+        if self.uses_arg.pop().unwrap() {
+            // The implicit `arg` of an enclosing function would shadow it too
+            if let Some(enclosing) = self.uses_arg.last_mut() {
+                *enclosing = true;
+            }
+        } else {
+            body.is_vararg = true;
+        }
+    }
+
+    fn visit_statement(&mut self, stmt: &mut Statement) {
+        if let StatementKind::FunctionDeclaration { target, .. } = &mut stmt.kind {
+            self.visit_expression(target);
+        }
+        walk_statement(self, stmt);
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        if self.compat_arg
+            && let ExpressionKind::Variable(name) = &expr.kind
+            && name == "arg"
+            && let Some(uses_arg) = self.uses_arg.last_mut()
+        {
+            *uses_arg = true;
+        }
+        walk_expression(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn varargs(source: &str, compat_arg: bool) -> Vec<bool> {
+        let tokens = tokenize(source, LuaVersion::Lua51).unwrap();
+        let mut ast = parse(&tokens, LuaVersion::Lua51).unwrap().ast;
+        visit_ast(&mut Adder { compat_arg, uses_arg: Vec::new() }, &mut ast);
+        let mut collector = Varargs(Vec::new());
+        visit_ast(&mut collector, &mut ast);
+        collector.0
+    }
+
+    struct Varargs(Vec<bool>);
+
+    impl VisitorMut for Varargs {
+        fn visit_function_body(&mut self, body: &mut FunctionBody) {
+            self.0.push(body.is_vararg);
+            walk_function_body(self, body);
+        }
+    }
+
+    #[test]
+    fn functions_using_arg_are_skipped() {
+        let source = "local function a(x) return function() return arg end end \
+                      local function b(arg) end function c() function arg.x() end end \
+                      local d = function(y) return y end";
+        assert_eq!(varargs(source, true), [false, false, false, false, true, true]);
+        assert_eq!(varargs(source, false), [true; 6]);
+    }
+}
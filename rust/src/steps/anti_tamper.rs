@@ -0,0 +1,165 @@
+//! Transformation behind the [`AntiTamper`] step.
+//!
+//! A block of integrity checks is prepended to the chunk. The portable checks
+//! verify that `pcall`, `error` and varargs behave like the originals and, in
+//! minified output where the whole script is on a single line, that errors
+//! raised from different statements report the same position. With the debug
+//! library the checks also verify that library functions are still native,
+//! compare a checksum over the lines reported by a line hook and check the
+//! shape of a traceback. LuaU lacks most of the debug library, so LuaU output
+//! only gets the portable checks. If any check fails, execution stops with an
+//! error.
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::ast::AstNode;
+use crate::lua::LuaVersion;
+use crate::parser::parse_snippet;
+use crate::pipeline::Pipeline;
+
+use super::AntiTamper;
+
+/// Checks prepended to the chunk. The placeholders in capitals are filled in
+/// by [`checks`].
+const CHECKS: &str = r##"
+do
+	local valid, pcall, error, select, type = true, pcall, error, select, type
+	local find, sub = string.find, string.sub
+	local unpack = table and table.unpack or unpack
+	DEBUG_CHECKS
+	local ok, message = pcall(error, "MARK", 0)
+	if ok or message ~= "MARK" then
+		valid = false
+	end
+	local object = {}
+	ok, message = pcall(error, object)
+	if ok or message ~= object then
+		valid = false
+	end
+	local values = {VALUES}
+	local count, last = select("#", unpack(values)), values[#values]
+	ok, message = pcall(function(...)
+		return select("#", ...) == count and select(count, ...) == last
+	end, unpack(values))
+	if not ok or not message then
+		valid = false
+	end
+	LINE_CHECKS
+	if not valid then
+		error("Tamper Detected!", 0)
+		return (nil)()
+	end
+end
+"##;
+
+/// Checks that need the debug library.
+const DEBUG_CHECKS: &str = r##"
+	local getinfo, sethook, traceback, getupvalue, dump = debug.getinfo, debug.sethook, debug.traceback, debug.getupvalue, string.dump
+	local natives = {pcall, error, select, type, find, sub, getinfo, sethook, traceback, getupvalue, dump}
+	for i = 1, #natives do
+		local native = natives[i]
+		if type(native) ~= "function" or getinfo(native, "S").what ~= "C" or getupvalue(native, 1) ~= nil or pcall(dump, native) then
+			valid = false
+		end
+	end
+	local function trace()
+		return traceback("MARK", 1), getinfo(1, "Sl")
+	end
+	local stack, info = trace()
+	if find(stack, "MARK\nstack traceback:\n", 1, true) ~= 1 or not find(stack, info.short_src .. ":" .. info.currentline .. ":", 1, true) then
+		valid = false
+	end
+"##;
+
+/// Line checks for minified output, where every line is the same.
+const LINE_CHECKS: &str = r##"
+	local positions = {}
+	for i = 1, PROBES do
+		local _, message = pcall(function()
+			error("MARK")
+		end)
+		positions[i] = sub(message, 1, #message - #"MARK")
+	end
+	local _, message = pcall(function() error("MARK") end)
+	if sub(message, 1, #message - #"MARK") ~= positions[1] then
+		valid = false
+	end
+	for i = 2, PROBES do
+		if positions[i] ~= positions[1] then
+			valid = false
+		end
+	end
+	HOOK_CHECKS
+"##;
+
+/// Checksum over the lines reported by a line hook while probes run.
+const HOOK_CHECKS: &str = r##"
+	local events, sum = 0, 0
+	local function probe() end
+	local line = getinfo(1, "l").currentline
+	sethook(function(_, current)
+		events = events + 1
+		sum = sum + current
+	end, "l")
+	PROBE_CALLS
+	sethook()
+	if events < PROBES or sum ~= events * line then
+		valid = false
+	end
+"##;
+
+pub(super) fn apply(step: &AntiTamper, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let mut rng = pipeline.rng("AntiTamper");
+    let source = checks(step, pipeline.lua_version, pipeline.pretty_print, &mut rng);
+    let mut statements = parse_snippet(&source).statements;
+    statements.append(&mut ast.block.statements);
+    ast.block.statements = statements;
+    ast
+}
+
+/// Source of the checks. Line checks are left out of pretty printed output,
+/// whose statements are on different lines.
+fn checks(step: &AntiTamper, lua_version: LuaVersion, pretty_print: bool, rng: &mut impl Rng) -> String {
+    let use_debug = step.use_debug && !matches!(lua_version, LuaVersion::LuaU);
+    let probes = rng.gen_range(3..=8);
+    let mut line_checks = String::new();
+    if !pretty_print {
+        line_checks = LINE_CHECKS.replace(
+            "HOOK_CHECKS",
+            if use_debug { HOOK_CHECKS } else { "" },
+        );
+    }
+    let values: Vec<String> =
+        (0..rng.gen_range(4..=12)).map(|_| rng.gen_range(0..=255).to_string()).collect();
+    let mark: String = (0..rng.gen_range(8..=16)).map(|_| rng.sample(Alphanumeric) as char).collect();
+    CHECKS
+        .replace("DEBUG_CHECKS", if use_debug { DEBUG_CHECKS } else { "" })
+        .replace("LINE_CHECKS", &line_checks)
+        .replace("PROBE_CALLS", &vec!["probe()"; probes].join(" "))
+        .replace("PROBES", &probes.to_string())
+        .replace("VALUES", &values.join(", "))
+        .replace("MARK", &mark)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn checks_parse_for_every_setting() {
+        for lua_version in [LuaVersion::Lua51, LuaVersion::LuaU] {
+            for use_debug in [false, true] {
+                for pretty_print in [false, true] {
+                    let step = AntiTamper { use_debug };
+                    let source = checks(&step, lua_version, pretty_print, &mut StdRng::seed_from_u64(1));
+                    assert!(!source.contains("MARK") && !source.contains("PROBE"), "{source}");
+                    assert_eq!(source.contains("debug."), use_debug && matches!(lua_version, LuaVersion::Lua51));
+                    assert_eq!(parse_snippet(&source).statements.len(), 1);
+                }
+            }
+        }
+    }
+}
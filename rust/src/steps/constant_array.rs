@@ -0,0 +1,389 @@
+//! Transformation behind the [`ConstantArray`] step.
+//!
+//! Constants are replaced by lookups into a single array that is declared at
+//! the top of the chunk. The array is stored shuffled, rotated and encoded;
+//! code emitted right after its declaration undoes the rotation and decodes
+//! the strings before the rest of the script runs. With local wrappers the
+//! lookups call small accessor functions with shifted arguments instead of
+//! indexing the array with the plain position.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement, StatementKind, TableField,
+};
+use crate::config::IDENT_PREFIX;
+use crate::pipeline::Pipeline;
+use crate::util::lua_bytes;
+use crate::visitor::{visit_ast, walk_expression, walk_function_body, VisitorMut};
+
+use super::{snippet, unused_suffix, ConstantArray};
+
+/// Name used for the array in the runtime snippets.
+const ARRAY: &str = "ARRAY";
+
+/// Array name used by [`Collector`] until the positions are known. It is not
+/// a valid identifier, so it cannot clash with lookups emitted by an earlier
+/// run of the step.
+const PENDING: &str = "<constant>";
+
+/// Undoes the rotation. `SHIFT` is replaced by the rotation amount.
+const UNROTATE: &str = r#"
+do
+	local array = ARRAY
+	local function reverse(i, j)
+		while i < j do
+			array[i], array[j] = array[j], array[i]
+			i, j = i + 1, j - 1
+		end
+	end
+	reverse(1, #array)
+	reverse(1, SHIFT)
+	reverse(SHIFT + 1, #array)
+end
+"#;
+
+/// Decodes the base64 strings in place. `ALPHABET` is replaced by the digits
+/// used by [`base64`].
+const DECODE_BASE64: &str = r#"
+do
+	local array = ARRAY
+	local sub, char, concat, floor, type = string.sub, string.char, table.concat, math.floor, type
+	local alphabet = "ALPHABET"
+	local lookup = {}
+	for i = 1, 64 do
+		lookup[sub(alphabet, i, i)] = i - 1
+	end
+	for i = 1, #array do
+		local data = array[i]
+		if type(data) == "string" then
+			local parts = {}
+			local value, count = 0, 0
+			for j = 1, #data do
+				local digit = lookup[sub(data, j, j)]
+				if digit then
+					value = value * 64 + digit
+					count = count + 1
+					if count == 4 then
+						parts[#parts + 1] = char(floor(value / 65536), floor(value / 256) % 256, value % 256)
+						value, count = 0, 0
+					end
+				end
+			end
+			if count == 3 then
+				parts[#parts + 1] = char(floor(value / 1024), floor(value / 4) % 256)
+			elseif count == 2 then
+				parts[#parts + 1] = char(floor(value / 16))
+			end
+			array[i] = concat(parts)
+		end
+	end
+end
+"#;
+
+const BASE64_DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, PartialEq)]
+enum Constant {
+    Number(f64),
+    String(String),
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(String),
+}
+
+impl Constant {
+    fn key(&self) -> ConstantKey {
+        match self {
+            Constant::Number(n) => ConstantKey::Number(n.to_bits()),
+            Constant::String(s) => ConstantKey::String(s.clone()),
+        }
+    }
+}
+
+pub(super) fn apply(step: &ConstantArray, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let rng = &mut pipeline.rng("ConstantArray");
+    // The wrappers share the suffix, so they are unused as well
+    let run = unused_suffix(pipeline, "constants");
+    let name = format!("{IDENT_PREFIX}constants{run}");
+
+    let mut collector = Collector {
+        step,
+        rng: &mut *rng,
+        constants: Vec::new(),
+        slots: HashMap::new(),
+    };
+    visit_ast(&mut collector, &mut ast);
+    let constants = collector.constants;
+    if constants.is_empty() {
+        return ast;
+    }
+
+    // `order[i]` is the slot stored at position `i` once the array is restored
+    let mut order: Vec<usize> = (0..constants.len()).collect();
+    if step.shuffle {
+        order.shuffle(rng);
+    }
+    let mut positions = vec![0; order.len()];
+    for (position, &slot) in order.iter().enumerate() {
+        positions[slot] = position as i64 + 1;
+    }
+    let mut lookups = Lookups {
+        step,
+        rng: &mut *rng,
+        array: &name,
+        wrappers_name: &format!("{IDENT_PREFIX}wrappers{run}"),
+        positions: &positions,
+        scopes: Vec::new(),
+    };
+    let wrappers = lookups.wrappers();
+    lookups.scopes.push(wrappers);
+    visit_ast(&mut lookups, &mut ast);
+    let wrappers = lookups.scopes.pop().unwrap();
+    let main_wrappers = (!wrappers.is_empty()).then(|| lookups.declaration(&wrappers));
+
+    let has_strings = constants.iter().any(|c| matches!(c, Constant::String(_)));
+    let alphabet = (step.encoding == "base64" && has_strings).then(|| {
+        let mut digits = *BASE64_DIGITS;
+        digits.shuffle(rng);
+        digits
+    });
+    let mut values: Vec<TableField> = order
+        .iter()
+        .map(|&slot| {
+            let value = match &constants[slot] {
+                Constant::Number(n) => ExpressionKind::Number(*n),
+                Constant::String(s) => match &alphabet {
+                    Some(digits) => ExpressionKind::String(base64(&lua_bytes(s), digits)),
+                    None => ExpressionKind::String(s.clone()),
+                },
+            };
+            TableField::Value(value.into())
+        })
+        .collect();
+    let shift = (step.rotate && values.len() > 1).then(|| rng.gen_range(1..values.len()));
+    if let Some(shift) = shift {
+        values.rotate_left(shift);
+    }
+
+    let mut prologue = vec![StatementKind::LocalAssignment {
+        names: vec![name.clone()],
+        exprs: vec![ExpressionKind::Table(values).into()],
+    }
+    .into()];
+    if let Some(shift) = shift {
+        prologue.extend(snippet(&UNROTATE.replace("SHIFT", &shift.to_string()), &[(ARRAY, &name)]));
+    }
+    if let Some(digits) = &alphabet {
+        let digits = std::str::from_utf8(digits).unwrap();
+        prologue.extend(snippet(&DECODE_BASE64.replace("ALPHABET", digits), &[(ARRAY, &name)]));
+    }
+    prologue.extend(main_wrappers);
+    prologue.append(&mut ast.block.statements);
+    ast.block.statements = prologue;
+    ast
+}
+
+/// Replaces the selected constants with `<constant>[slot]`, where `slot`
+/// indexes `constants`. [`Lookups`] later turns these into array lookups.
+struct Collector<'a, R> {
+    step: &'a ConstantArray,
+    rng: &'a mut R,
+    constants: Vec<Constant>,
+    slots: HashMap<ConstantKey, usize>,
+}
+
+impl<R: Rng> VisitorMut for Collector<'_, R> {
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        let constant = match &expr.kind {
+            ExpressionKind::String(s) => Constant::String(s.clone()),
+            ExpressionKind::Number(n) if !self.step.strings_only => Constant::Number(*n),
+            _ => return walk_expression(self, expr),
+        };
+        if self.rng.r#gen::<f64>() > self.step.treshold {
+            return;
+        }
+        let slot = *self.slots.entry(constant.key()).or_insert_with(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        });
+        expr.kind = index(PENDING, slot as f64);
+    }
+}
+
+/// Local accessor `wrappers.key(...)` that returns `array[args[arg] + offset]`.
+struct Wrapper {
+    key: String,
+    arg: usize,
+    offset: i64,
+}
+
+/// Turns the placeholders left by [`Collector`] into array lookups. Functions
+/// that were picked for local wrappers declare a table of [`Wrapper`]s, and
+/// lookups within them, including nested functions without wrappers of their
+/// own, go through a random one of the closest wrappers.
+struct Lookups<'a, R> {
+    step: &'a ConstantArray,
+    rng: &'a mut R,
+    array: &'a str,
+    wrappers_name: &'a str,
+    positions: &'a [i64],
+    /// Wrappers of every enclosing function, empty for those without.
+    scopes: Vec<Vec<Wrapper>>,
+}
+
+impl<R: Rng> Lookups<'_, R> {
+    /// Wrappers for a new function; none unless it is picked.
+    fn wrappers(&mut self) -> Vec<Wrapper> {
+        if self.step.local_wrapper_count == 0
+            || self.rng.r#gen::<f64>() > self.step.local_wrapper_treshold
+        {
+            return Vec::new();
+        }
+        let max_offset = self.step.max_wrapper_offset as i64;
+        let mut wrappers: Vec<Wrapper> = Vec::new();
+        while wrappers.len() < self.step.local_wrapper_count as usize {
+            let key = random_key(self.rng);
+            if wrappers.iter().any(|wrapper| wrapper.key == key) {
+                continue;
+            }
+            wrappers.push(Wrapper {
+                key,
+                arg: self.rng.gen_range(0..self.step.local_wrapper_arg_count.max(1) as usize),
+                offset: self.rng.gen_range(-max_offset..=max_offset),
+            });
+        }
+        wrappers
+    }
+
+    /// `local wrappers = { key = function(...) return array[arg + offset] end }`
+    fn declaration(&self, wrappers: &[Wrapper]) -> Statement {
+        let params: Vec<String> = (0..self.step.local_wrapper_arg_count.max(1))
+            .map(|i| format!("{IDENT_PREFIX}arg{i}"))
+            .collect();
+        let fields = wrappers
+            .iter()
+            .map(|wrapper| {
+                let position = ExpressionKind::BinaryOp {
+                    left: Box::new(ExpressionKind::Variable(params[wrapper.arg].clone()).into()),
+                    op: "+".to_string(),
+                    right: Box::new(ExpressionKind::Number(wrapper.offset as f64).into()),
+                };
+                let lookup = ExpressionKind::Index {
+                    object: Box::new(ExpressionKind::Variable(self.array.to_string()).into()),
+                    key: Box::new(position.into()),
+                };
+                let block = Block::new(vec![StatementKind::Return(vec![lookup.into()]).into()]);
+                let function = ExpressionKind::Function(FunctionBody::new(params.clone(), false, block));
+                TableField::Named { name: wrapper.key.clone(), value: function.into() }
+            })
+            .collect();
+        StatementKind::LocalAssignment {
+            names: vec![self.wrappers_name.to_string()],
+            exprs: vec![ExpressionKind::Table(fields).into()],
+        }
+        .into()
+    }
+
+    fn lookup(&mut self, position: i64) -> ExpressionKind {
+        let Some(wrappers) = self.scopes.iter().rev().find(|wrappers| !wrappers.is_empty()) else {
+            return index(self.array, position as f64);
+        };
+        let wrapper = &wrappers[self.rng.gen_range(0..wrappers.len())];
+        let max_offset = self.step.max_wrapper_offset as i64;
+        let args = (0..self.step.local_wrapper_arg_count.max(1) as usize)
+            .map(|i| {
+                let value = if i == wrapper.arg {
+                    position - wrapper.offset
+                } else {
+                    position + self.rng.gen_range(-max_offset..=max_offset)
+                };
+                ExpressionKind::Number(value as f64).into()
+            })
+            .collect();
+        let func = ExpressionKind::Index {
+            object: Box::new(ExpressionKind::Variable(self.wrappers_name.to_string()).into()),
+            key: Box::new(ExpressionKind::String(wrapper.key.clone()).into()),
+        };
+        ExpressionKind::Call { func: Box::new(func.into()), args }
+    }
+}
+
+impl<R: Rng> VisitorMut for Lookups<'_, R> {
+    fn visit_function_body(&mut self, body: &mut FunctionBody) {
+        let wrappers = self.wrappers();
+        self.scopes.push(wrappers);
+        walk_function_body(self, body);
+        let wrappers = self.scopes.pop().unwrap();
+        if !wrappers.is_empty() {
+            body.block.statements.insert(0, self.declaration(&wrappers));
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        if let ExpressionKind::Index { object, key } = &expr.kind
+            && matches!(&object.kind, ExpressionKind::Variable(name) if name == PENDING)
+            && let ExpressionKind::Number(slot) = key.kind
+        {
+            expr.kind = self.lookup(self.positions[slot as usize]);
+            return;
+        }
+        walk_expression(self, expr);
+    }
+}
+
+/// Random identifier used as the key of a [`Wrapper`]. It starts with an
+/// uppercase letter, so it is never a keyword.
+fn random_key(rng: &mut impl Rng) -> String {
+    const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let first = LETTERS[rng.gen_range(26..LETTERS.len())] as char;
+    let len = rng.gen_range(3..=7);
+    std::iter::once(first)
+        .chain((0..len).map(|_| LETTERS[rng.gen_range(0..LETTERS.len())] as char))
+        .collect()
+}
+
+fn index(array: &str, position: f64) -> ExpressionKind {
+    ExpressionKind::Index {
+        object: Box::new(ExpressionKind::Variable(array.to_string()).into()),
+        key: Box::new(ExpressionKind::Number(position).into()),
+    }
+}
+
+/// Base64 encode `bytes` with the given digits.
+fn base64(bytes: &[u8], digits: &[u8; 64]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |value, (i, &byte)| value | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(digits[(value >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_standard_encoding() {
+        assert_eq!(base64(b"Man", BASE64_DIGITS), "TWFu");
+        assert_eq!(base64(b"Ma", BASE64_DIGITS), "TWE=");
+        assert_eq!(base64(b"M", BASE64_DIGITS), "TQ==");
+        assert_eq!(base64(b"", BASE64_DIGITS), "");
+    }
+}
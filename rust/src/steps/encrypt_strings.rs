@@ -0,0 +1,242 @@
+//! Transformation behind the [`EncryptStrings`] step.
+//!
+//! Every string literal is replaced by `decrypt(ciphertext, key)`. The
+//! decryptor is emitted at the top of the chunk and regenerates the keystream
+//! of the selected cipher from the key. Each byte is combined with the
+//! keystream and the previous ciphertext byte, so equal plaintext bytes do not
+//! encrypt to equal ciphertext bytes.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::ast::{AstNode, Expression, ExpressionKind, StatementKind};
+use crate::config::IDENT_PREFIX;
+use crate::pipeline::Pipeline;
+use crate::util::lua_bytes;
+use crate::visitor::{visit_ast, walk_expression, VisitorMut};
+
+use super::{snippet, unused_suffix, EncryptStrings};
+
+/// Keys are below `2^45`, so the LCG state times its multiplier stays exact
+/// in a double.
+const KEY_LIMIT: u64 = 1 << 45;
+
+/// Defines the decryptor. `DECRYPT` names the local that receives it; the
+/// other placeholders in capitals are filled in by [`decryptor`].
+const DECRYPT: &str = r#"
+do
+	local byte, char, floor, concat = string.byte, string.char, math.floor, table.concat
+	local cache = {}
+	DECRYPT = function(data, key)
+		CACHE_LOOKUP
+		local keystream = KEYSTREAM
+		local parts = {}
+		local previous = key % 256
+		for i = 1, #data do
+			local value = byte(data, i)
+			parts[i] = char((value - keystream() - previous) % 256)
+			previous = value
+		end
+		local result = concat(parts)
+		CACHE_STORE
+		return result
+	end
+end
+"#;
+
+const LCG_KEYSTREAM: &str = r#"(function()
+	local state = key
+	return function()
+		state = (state * 173 + 8408159861491) % 35184372088832
+		return floor(state / 137438953472) % 256
+	end
+end)()"#;
+
+const RC4_KEYSTREAM: &str = r#"(function()
+	local S, K = {}, {}
+	local rest = key
+	for i = 0, 5 do
+		K[i] = rest % 256
+		rest = floor(rest / 256)
+	end
+	for i = 0, 255 do
+		S[i] = i
+	end
+	local j = 0
+	for i = 0, 255 do
+		j = (j + S[i] + K[i % 6]) % 256
+		S[i], S[j] = S[j], S[i]
+	end
+	local i = 0
+	j = 0
+	return function()
+		i = (i + 1) % 256
+		j = (j + S[i]) % 256
+		S[i], S[j] = S[j], S[i]
+		return S[(S[i] + S[j]) % 256]
+	end
+end)()"#;
+
+/// Keystream generator of a cipher, mirroring the Lua code in the decryptor.
+trait Keystream {
+    fn next_byte(&mut self) -> u8;
+}
+
+struct Lcg {
+    state: u64,
+}
+
+impl Keystream for Lcg {
+    fn next_byte(&mut self) -> u8 {
+        self.state = (self.state * 173 + 8_408_159_861_491) % KEY_LIMIT;
+        (self.state >> 37) as u8
+    }
+}
+
+struct Rc4 {
+    s: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: u64) -> Self {
+        let key: Vec<u8> = (0..6).map(|i| (key >> (8 * i)) as u8).collect();
+        let mut s = [0u8; 256];
+        for (i, value) in s.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(s[i]).wrapping_add(key[i % 6]);
+            s.swap(i, j as usize);
+        }
+        Rc4 { s, i: 0, j: 0 }
+    }
+}
+
+impl Keystream for Rc4 {
+    fn next_byte(&mut self) -> u8 {
+        self.i = self.i.wrapping_add(1);
+        self.j = self.j.wrapping_add(self.s[self.i as usize]);
+        self.s.swap(self.i as usize, self.j as usize);
+        self.s[self.s[self.i as usize].wrapping_add(self.s[self.j as usize]) as usize]
+    }
+}
+
+fn keystream(cipher: &str, key: u64) -> Box<dyn Keystream> {
+    match cipher {
+        "rc4" => Box::new(Rc4::new(key)),
+        _ => Box::new(Lcg { state: key }),
+    }
+}
+
+/// Encrypt the Lua string `s` the way the decryptor expects it. The result
+/// holds one char per byte, like strings produced by the lexer.
+fn encrypt(cipher: &str, s: &str, key: u64) -> String {
+    let mut stream = keystream(cipher, key);
+    let mut previous = key as u8;
+    lua_bytes(s)
+        .into_iter()
+        .map(|byte| {
+            let value = byte.wrapping_add(stream.next_byte()).wrapping_add(previous);
+            previous = value;
+            value as char
+        })
+        .collect()
+}
+
+/// Source of the decryptor function for the step's settings.
+fn decryptor(step: &EncryptStrings) -> String {
+    let (lookup, store) = if step.use_cache {
+        ("local cached = cache[key] if cached then return cached end", "cache[key] = result")
+    } else {
+        ("", "")
+    };
+    let keystream = match step.cipher.as_str() {
+        "rc4" => RC4_KEYSTREAM,
+        _ => LCG_KEYSTREAM,
+    };
+    DECRYPT
+        .replace("CACHE_LOOKUP", lookup)
+        .replace("CACHE_STORE", store)
+        .replace("KEYSTREAM", keystream)
+}
+
+pub(super) fn apply(step: &EncryptStrings, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let decrypt = format!("{IDENT_PREFIX}decrypt{}", unused_suffix(pipeline, "decrypt"));
+    let mut encryptor = Encryptor {
+        step,
+        rng: pipeline.rng("EncryptStrings"),
+        decrypt: &decrypt,
+        strings: HashMap::new(),
+        keys: HashSet::new(),
+    };
+    visit_ast(&mut encryptor, &mut ast);
+    if encryptor.strings.is_empty() {
+        return ast;
+    }
+
+    let mut prologue = vec![StatementKind::LocalAssignment {
+        names: vec![decrypt.clone()],
+        exprs: Vec::new(),
+    }
+    .into()];
+    prologue.extend(snippet(&decryptor(step), &[("DECRYPT", &decrypt)]));
+    prologue.append(&mut ast.block.statements);
+    ast.block.statements = prologue;
+    ast
+}
+
+/// Replaces string literals with calls to the decryptor. Equal strings share
+/// a key and ciphertext; distinct strings never share a key, which the cache
+/// relies on.
+struct Encryptor<'a, R> {
+    step: &'a EncryptStrings,
+    rng: R,
+    decrypt: &'a str,
+    strings: HashMap<String, (String, u64)>,
+    keys: HashSet<u64>,
+}
+
+impl<R: Rng> VisitorMut for Encryptor<'_, R> {
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        let ExpressionKind::String(s) = &expr.kind else {
+            return walk_expression(self, expr);
+        };
+        if !self.strings.contains_key(s) {
+            let key = loop {
+                let key = self.rng.gen_range(0..KEY_LIMIT);
+                if self.keys.insert(key) {
+                    break key;
+                }
+            };
+            let ciphertext = encrypt(&self.step.cipher, s, key);
+            self.strings.insert(s.clone(), (ciphertext, key));
+        }
+        let (ciphertext, key) = &self.strings[s];
+        expr.kind = ExpressionKind::Call {
+            func: Box::new(ExpressionKind::Variable(self.decrypt.to_string()).into()),
+            args: vec![
+                ExpressionKind::String(ciphertext.clone()).into(),
+                ExpressionKind::Number(*key as f64).into(),
+            ],
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciphertext_differs_from_plaintext() {
+        for cipher in ["lcg", "rc4"] {
+            let encrypted = encrypt(cipher, "aaaaaaaa", 12345);
+            assert_eq!(encrypted.chars().count(), 8);
+            assert_ne!(encrypted, "aaaaaaaa");
+            assert_ne!(encrypt(cipher, "aaaaaaaa", 12346), encrypted);
+        }
+    }
+}
@@ -2,9 +2,23 @@ use std::collections::HashMap;
 
 use serde_json::Value;
 
-use crate::ast::AstNode;
+use crate::ast::{AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement, StatementKind};
+use crate::compiler;
+use crate::config::IDENT_PREFIX;
+use crate::error::PrometheusError;
+use crate::parser::parse_snippet;
 use crate::pipeline::Pipeline;
 use crate::step::{SettingDescriptor, Step};
+use crate::visitor::{visit_ast, walk_expression, VisitorMut};
+
+mod add_vararg;
+mod anti_tamper;
+mod constant_array;
+mod encrypt_strings;
+mod numbers_to_expressions;
+mod proxify_locals;
+mod split_strings;
+mod watermark;
 
 // ---------------------------------------------------------------------------
 // ConstantArray
@@ -47,15 +61,18 @@ impl ConstantArray {
                 .unwrap_or(1.0),
             local_wrapper_count: settings
                 .get("LocalWrapperCount")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(0),
             local_wrapper_arg_count: settings
                 .get("LocalWrapperArgCount")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(10),
             max_wrapper_offset: settings
                 .get("MaxWrapperOffset")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(65535),
             encoding: settings
                 .get("Encoding")
@@ -76,8 +93,8 @@ impl Step for ConstantArray {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &CONSTANT_ARRAY_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(constant_array::apply(self, ast, pipeline))
     }
 }
 
@@ -111,21 +128,21 @@ const CONSTANT_ARRAY_SETTINGS: [SettingDescriptor; 9] = [
         Some(0.0),
         Some(1.0),
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "LocalWrapperCount",
         "The number of Local wrapper Functions per scope. This only applies if LocalWrapperTreshold is greater than 0",
         0.0,
         Some(0.0),
         Some(512.0),
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "LocalWrapperArgCount",
         "The number of Arguments to the Local wrapper Functions",
         10.0,
         Some(1.0),
         Some(200.0),
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "MaxWrapperOffset",
         "The Max Offset for the Wrapper Functions",
         65535.0,
@@ -153,7 +170,8 @@ impl WrapInFunction {
         Self {
             iterations: settings
                 .get("Iterations")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(1),
         }
     }
@@ -169,12 +187,23 @@ impl Step for WrapInFunction {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &WRAP_IN_FUNCTION_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, mut ast: AstNode, _pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        // `return (function(...) <block> end)(...)` passes the chunk's varargs
+        // in and all of its return values out
+        for _ in 0..self.iterations.max(1) {
+            let block = std::mem::replace(&mut ast.block, Block::new(Vec::new()));
+            let function = ExpressionKind::Function(FunctionBody::new(Vec::new(), true, block));
+            let call = ExpressionKind::Call {
+                func: Box::new(ExpressionKind::Paren(Box::new(function.into())).into()),
+                args: vec![ExpressionKind::Vararg.into()],
+            };
+            ast.block.statements.push(StatementKind::Return(vec![call.into()]).into());
+        }
+        Ok(ast)
     }
 }
 
-const WRAP_IN_FUNCTION_SETTINGS: [SettingDescriptor; 1] = [SettingDescriptor::number(
+const WRAP_IN_FUNCTION_SETTINGS: [SettingDescriptor; 1] = [SettingDescriptor::integer(
     "Iterations",
     "The Number Of Iterations",
     1.0,
@@ -211,8 +240,8 @@ impl Step for AntiTamper {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &ANTI_TAMPER_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(anti_tamper::apply(self, ast, pipeline))
     }
 }
 
@@ -244,8 +273,8 @@ impl Step for AddVararg {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &[]
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(add_vararg::apply(ast, pipeline))
     }
 }
 
@@ -283,8 +312,8 @@ impl Step for NumbersToExpressions {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &NUMBERS_TO_EXPRESSIONS_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(numbers_to_expressions::apply(self, ast, pipeline))
     }
 }
 
@@ -327,11 +356,13 @@ impl SplitStrings {
                 .unwrap_or(1.0),
             min_length: settings
                 .get("MinLength")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(5),
             max_length: settings
                 .get("MaxLength")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(5),
             concatenation_type: settings
                 .get("ConcatenationType")
@@ -345,7 +376,8 @@ impl SplitStrings {
                 .to_string(),
             custom_local_functions_count: settings
                 .get("CustomLocalFunctionsCount")
-                .and_then(Value::as_u64)
+                .and_then(Value::as_f64)
+                .map(|value| value as u64)
                 .unwrap_or(2),
         }
     }
@@ -361,8 +393,8 @@ impl Step for SplitStrings {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &SPLIT_STRINGS_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(split_strings::apply(self, ast, pipeline))
     }
 }
 
@@ -374,14 +406,14 @@ const SPLIT_STRINGS_SETTINGS: [SettingDescriptor; 6] = [
         Some(0.0),
         Some(1.0),
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "MinLength",
         "The minimal length for the chunks in that the Strings are splitted",
         5.0,
         Some(1.0),
         None,
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "MaxLength",
         "The maximal length for the chunks in that the Strings are splitted",
         5.0,
@@ -400,7 +432,7 @@ const SPLIT_STRINGS_SETTINGS: [SettingDescriptor; 6] = [
         "global",
         &["global", "local", "inline"],
     ),
-    SettingDescriptor::number(
+    SettingDescriptor::integer(
         "CustomLocalFunctionsCount",
         "The number of local functions per scope. This option only applies when CustomFunctionType = local",
         2.0,
@@ -445,8 +477,8 @@ impl Step for Watermark {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &WATERMARK_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        watermark::apply(self, ast, pipeline)
     }
 }
 
@@ -467,11 +499,24 @@ const WATERMARK_SETTINGS: [SettingDescriptor; 2] = [
 // EncryptStrings
 // ---------------------------------------------------------------------------
 
-pub struct EncryptStrings;
+pub struct EncryptStrings {
+    pub cipher: String,
+    pub use_cache: bool,
+}
 
 impl EncryptStrings {
-    pub fn new(_settings: &HashMap<String, Value>) -> Self {
-        EncryptStrings
+    pub fn new(settings: &HashMap<String, Value>) -> Self {
+        Self {
+            cipher: settings
+                .get("Cipher")
+                .and_then(Value::as_str)
+                .unwrap_or("lcg")
+                .to_string(),
+            use_cache: settings
+                .get("UseCache")
+                .and_then(Value::as_bool)
+                .unwrap_or(true),
+        }
     }
 }
 
@@ -483,13 +528,27 @@ impl Step for EncryptStrings {
         "This Step will encrypt strings within your Program."
     }
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
-        &[]
+        &ENCRYPT_STRINGS_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(encrypt_strings::apply(self, ast, pipeline))
     }
 }
 
+const ENCRYPT_STRINGS_SETTINGS: [SettingDescriptor; 2] = [
+    SettingDescriptor::enumeration(
+        "Cipher",
+        "The Cipher used to generate the Keystream",
+        "lcg",
+        &["lcg", "rc4"],
+    ),
+    SettingDescriptor::boolean(
+        "UseCache",
+        "Whether to cache decrypted Strings so that every String is only decrypted once",
+        true,
+    ),
+];
+
 // ---------------------------------------------------------------------------
 // ProxifyLocals
 // ---------------------------------------------------------------------------
@@ -520,8 +579,8 @@ impl Step for ProxifyLocals {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &PROXIFY_LOCALS_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(proxify_locals::apply(self, ast, pipeline))
     }
 }
 
@@ -554,8 +613,10 @@ impl Step for Vmify {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &[]
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        let proto = compiler::compile(&ast, pipeline.scope())
+            .map_err(|err| PrometheusError::step(self.name(), err.to_string()))?;
+        Ok(compiler::vm::emit(&proto, &mut pipeline.rng("Vmify")))
     }
 }
 
@@ -589,8 +650,8 @@ impl Step for WatermarkCheck {
     fn settings_descriptor(&self) -> &'static [SettingDescriptor] {
         &WATERMARK_CHECK_SETTINGS
     }
-    fn apply(&mut self, ast: AstNode, _pipeline: &Pipeline) -> AstNode {
-        ast
+    fn apply(&mut self, ast: AstNode, pipeline: &Pipeline) -> Result<AstNode, PrometheusError> {
+        Ok(watermark::apply_check(self, ast, pipeline))
     }
 }
 
@@ -600,6 +661,47 @@ const WATERMARK_CHECK_SETTINGS: [SettingDescriptor; 1] = [SettingDescriptor::str
     "This Script is Part of the Prometheus Obfuscator by Levno_710",
 )];
 
+// ---------------------------------------------------------------------------
+// Shared helpers
+// ---------------------------------------------------------------------------
+
+/// Smallest `n` such that no local of the current AST is named
+/// `{IDENT_PREFIX}{base}{n}`. Steps that declare locals at the top of the
+/// chunk use it so that the names of repeated runs do not shadow each other.
+fn unused_suffix(pipeline: &Pipeline, base: &str) -> usize {
+    let bindings = &pipeline.scope().bindings;
+    (0..)
+        .find(|n| {
+            let name = format!("{IDENT_PREFIX}{base}{n}");
+            bindings.iter().all(|binding| binding.name != name)
+        })
+        .unwrap()
+}
+
+/// Parse runtime support code, renaming the variables in `names` from their
+/// placeholder to the real name.
+fn snippet(source: &str, names: &[(&str, &str)]) -> Vec<Statement> {
+    struct Rename<'a> {
+        names: &'a [(&'a str, &'a str)],
+    }
+
+    impl VisitorMut for Rename<'_> {
+        fn visit_expression(&mut self, expr: &mut Expression) {
+            if let ExpressionKind::Variable(name) = &mut expr.kind {
+                if let Some((_, to)) = self.names.iter().find(|(from, _)| name == from) {
+                    *name = to.to_string();
+                }
+                return;
+            }
+            walk_expression(self, expr);
+        }
+    }
+
+    let mut ast = AstNode::new(parse_snippet(source));
+    visit_ast(&mut Rename { names }, &mut ast);
+    ast.block.statements
+}
+
 // ---------------------------------------------------------------------------
 // Registration helper
 // ---------------------------------------------------------------------------
@@ -0,0 +1,143 @@
+//! Transformation behind the [`NumbersToExpressions`] step.
+//!
+//! A number literal is replaced by a sum or difference of two numbers that
+//! evaluates to exactly the same value, and the operands may in turn be
+//! replaced by further expressions. Integral values are only split into
+//! integral operands, so Lua 5.4 still produces an integer; other values are
+//! only split when the floating point result is exact.
+
+use rand::Rng;
+
+use crate::ast::{AstNode, Expression, ExpressionKind};
+use crate::pipeline::Pipeline;
+use crate::visitor::{visit_ast, walk_expression, VisitorMut};
+
+use super::NumbersToExpressions;
+
+/// Deepest nesting of generated expressions.
+const MAX_DEPTH: usize = 15;
+
+/// Bound of the random operands.
+const OPERAND_RANGE: f64 = (1 << 20) as f64;
+
+/// Integral values above this bound are left alone, so that their operands
+/// stay exactly representable as doubles.
+const INTEGER_LIMIT: f64 = (1u64 << 52) as f64;
+
+pub(super) fn apply(step: &NumbersToExpressions, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let mut generator = Generator { step, rng: pipeline.rng("NumbersToExpressions") };
+    visit_ast(&mut generator, &mut ast);
+    ast
+}
+
+struct Generator<'a, R> {
+    step: &'a NumbersToExpressions,
+    rng: R,
+}
+
+impl<R: Rng> Generator<'_, R> {
+    /// Expression evaluating to `value`. Below the top level it only recurses
+    /// with probability `InternalTreshold`.
+    fn expression(&mut self, value: f64, depth: usize) -> ExpressionKind {
+        let stop = depth > 0 && self.rng.r#gen::<f64>() >= self.step.internal_treshold;
+        if stop || depth > MAX_DEPTH {
+            return ExpressionKind::Number(value);
+        }
+        let subtract = self.rng.r#gen::<bool>();
+        match self.operands(value, subtract) {
+            Some((left, right)) => ExpressionKind::BinaryOp {
+                left: Box::new(self.expression(left, depth + 1).into()),
+                op: if subtract { "-" } else { "+" }.to_string(),
+                right: Box::new(self.expression(right, depth + 1).into()),
+            },
+            None => ExpressionKind::Number(value),
+        }
+    }
+
+    /// Operands `(a, b)` with `a + b == value`, or `a - b == value` when
+    /// `subtract` is set.
+    fn operands(&mut self, value: f64, subtract: bool) -> Option<(f64, f64)> {
+        let integral = value.fract() == 0.0;
+        // `-0.0` would come back as `0`, and huge values lose precision
+        if !value.is_finite() || (value == 0.0 && value.is_sign_negative()) {
+            return None;
+        }
+        if integral && value.abs() > INTEGER_LIMIT {
+            return None;
+        }
+        let other = self.rng.gen_range(-OPERAND_RANGE..=OPERAND_RANGE).round();
+        let (left, right) = if subtract { (value + other, other) } else { (value - other, other) };
+        let result = if subtract { left - right } else { left + right };
+        // `-0.0` operands print as `0`, which could turn the sum positive
+        let signed_zero = |n: f64| n == 0.0 && n.is_sign_negative();
+        (result.to_bits() == value.to_bits() && !signed_zero(left) && !signed_zero(right))
+            .then_some((left, right))
+    }
+}
+
+impl<R: Rng> VisitorMut for Generator<'_, R> {
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        match expr.kind {
+            ExpressionKind::Number(value) => {
+                if self.rng.r#gen::<f64>() <= self.step.treshold {
+                    expr.kind = self.expression(value, 0);
+                }
+            }
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn evaluate(expr: &ExpressionKind) -> f64 {
+        match expr {
+            ExpressionKind::Number(n) => *n,
+            ExpressionKind::BinaryOp { left, op, right } => match op.as_str() {
+                "+" => evaluate(&left.kind) + evaluate(&right.kind),
+                "-" => evaluate(&left.kind) - evaluate(&right.kind),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_integral(expr: &ExpressionKind) -> bool {
+        match expr {
+            ExpressionKind::Number(n) => n.fract() == 0.0,
+            ExpressionKind::BinaryOp { left, right, .. } => {
+                is_integral(&left.kind) && is_integral(&right.kind)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn expressions_are_exact() {
+        let step = NumbersToExpressions { treshold: 1.0, internal_treshold: 0.8 };
+        let mut generator = Generator { step: &step, rng: StdRng::seed_from_u64(1) };
+        for value in [0.0, 1.0, -7.0, 0.1, 1e-300, 123456.789, 4503599627370496.0, f64::MAX] {
+            for _ in 0..50 {
+                let expr = generator.expression(value, 0);
+                assert_eq!(evaluate(&expr).to_bits(), value.to_bits(), "{expr:?}");
+                if value.fract() == 0.0 && value.abs() <= INTEGER_LIMIT {
+                    assert!(is_integral(&expr), "{expr:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exotic_values_are_kept() {
+        let step = NumbersToExpressions { treshold: 1.0, internal_treshold: 0.8 };
+        let mut generator = Generator { step: &step, rng: StdRng::seed_from_u64(1) };
+        for value in [-0.0, f64::INFINITY, 9007199254740994.0] {
+            let expr = generator.expression(value, 0);
+            assert!(matches!(expr, ExpressionKind::Number(n) if n.to_bits() == value.to_bits()));
+        }
+    }
+}
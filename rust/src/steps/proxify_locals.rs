@@ -0,0 +1,415 @@
+//! Transformation behind the [`ProxifyLocals`] step.
+//!
+//! Every `local` and `local function` is replaced by a proxy table whose
+//! metatable stores the real value under a random key. Reads go through one
+//! metamethod, e.g. `x + 123` or `x["abc"]`, and writes through another, e.g.
+//! `x .. value` or `x[456] = value`. Both metamethods and the literals are
+//! picked at random for every local.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement, StatementKind,
+    TableField,
+};
+use crate::config::IDENT_PREFIX;
+use crate::pipeline::Pipeline;
+use crate::random_literals;
+use crate::scope::{self, BindingId, BindingKind, Resolution, ScopeVisitor};
+use crate::visitor::{visit_ast, VisitorMut};
+
+use super::{unused_suffix, ProxifyLocals};
+
+/// Metamethods that can read the value, with the operator triggering them.
+const GETTERS: [(&str, &str); 8] = [
+    ("__index", ""),
+    ("__add", "+"),
+    ("__sub", "-"),
+    ("__mul", "*"),
+    ("__div", "/"),
+    ("__mod", "%"),
+    ("__pow", "^"),
+    ("__concat", ".."),
+];
+
+/// Metamethods that can write the value, with the operator triggering them.
+const SETTERS: [(&str, &str); 8] = [
+    ("__newindex", ""),
+    ("__add", "+"),
+    ("__sub", "-"),
+    ("__mul", "*"),
+    ("__div", "/"),
+    ("__mod", "%"),
+    ("__pow", "^"),
+    ("__concat", ".."),
+];
+
+/// How a single local is proxied.
+struct Proxy {
+    /// Key of the real value in the proxy table.
+    key: Expression,
+    /// Metamethod and operator reading the value; an empty operator indexes.
+    getter: (&'static str, &'static str),
+    /// Metamethod and operator writing the value; an empty operator assigns
+    /// to an index.
+    setter: (&'static str, &'static str),
+    /// Operand of the read.
+    get_literal: Expression,
+    /// Operand of the write when it assigns to an index.
+    set_literal: Expression,
+}
+
+impl Proxy {
+    fn new(literal_type: &str, rng: &mut impl Rng) -> Self {
+        let getter = *GETTERS.choose(rng).unwrap();
+        let setter = loop {
+            let setter = *SETTERS.choose(rng).unwrap();
+            if setter.0 != getter.0 {
+                break setter;
+            }
+        };
+        let mut literal = || match literal_type {
+            "dictionary" => random_literals::dictionary_literal(rng),
+            "number" => random_literals::number_literal(rng),
+            "any" => random_literals::any_literal(rng),
+            _ => random_literals::string_literal(rng),
+        };
+        Proxy { key: literal(), getter, setter, get_literal: literal(), set_literal: literal() }
+    }
+
+    /// Expression reading the value of `proxy`.
+    fn get(&self, proxy: Expression) -> ExpressionKind {
+        match self.getter.1 {
+            "" => ExpressionKind::Index {
+                object: Box::new(proxy),
+                key: Box::new(self.get_literal.clone()),
+            },
+            op => ExpressionKind::BinaryOp {
+                left: Box::new(proxy),
+                op: op.to_string(),
+                right: Box::new(self.get_literal.clone()),
+            },
+        }
+    }
+}
+
+/// Names of the locals introduced by one run of the step.
+struct Names {
+    proxy_prefix: String,
+    setmetatable: String,
+    rawget: String,
+    rawset: String,
+    discard: String,
+    value_prefix: String,
+}
+
+impl Names {
+    fn proxy(&self, id: BindingId) -> String {
+        format!("{}{}", self.proxy_prefix, id.0)
+    }
+
+    /// Binding behind the proxy named `name`.
+    fn binding(&self, name: &str) -> Option<BindingId> {
+        name.strip_prefix(&self.proxy_prefix)?.parse().ok().map(BindingId)
+    }
+}
+
+pub(super) fn apply(step: &ProxifyLocals, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let mut rng = pipeline.rng("ProxifyLocals");
+    let mut proxies = HashMap::new();
+    for (id, binding) in pipeline.scope().bindings.iter().enumerate() {
+        if matches!(binding.kind, BindingKind::Local | BindingKind::LocalFunction) {
+            proxies.insert(BindingId(id), Proxy::new(&step.literal_type, &mut rng));
+        }
+    }
+    if proxies.is_empty() {
+        return ast;
+    }
+
+    let run = unused_suffix(pipeline, "setmetatable");
+    let names = Names {
+        proxy_prefix: format!("{IDENT_PREFIX}proxy{run}_"),
+        setmetatable: format!("{IDENT_PREFIX}setmetatable{run}"),
+        rawget: format!("{IDENT_PREFIX}rawget{run}"),
+        rawset: format!("{IDENT_PREFIX}rawset{run}"),
+        discard: format!("{IDENT_PREFIX}discard{run}"),
+        value_prefix: format!("{IDENT_PREFIX}value{run}_"),
+    };
+    scope::resolve(&mut ast, &mut Redirect { proxies: &proxies, names: &names });
+    visit_ast(&mut Rewriter { proxies: &proxies, names: &names }, &mut ast);
+
+    let helpers = StatementKind::LocalAssignment {
+        names: vec![names.setmetatable.clone(), names.rawget.clone(), names.rawset.clone()],
+        exprs: vec![variable("setmetatable"), variable("rawget"), variable("rawset")],
+    };
+    ast.block.statements.insert(0, helpers.into());
+    ast
+}
+
+/// Renames the proxied locals and turns their reads into getter expressions.
+/// Assignment targets only get the new name and are rewritten by
+/// [`Rewriter`].
+struct Redirect<'a> {
+    proxies: &'a HashMap<BindingId, Proxy>,
+    names: &'a Names,
+}
+
+impl ScopeVisitor for Redirect<'_> {
+    fn declaration(&mut self, id: BindingId, name: &mut String) {
+        if self.proxies.contains_key(&id) {
+            *name = self.names.proxy(id);
+        }
+    }
+
+    fn reference(&mut self, resolution: Resolution, expr: &mut Expression) {
+        if let Resolution::Local(id) = resolution
+            && let Some(proxy) = self.proxies.get(&id)
+        {
+            expr.kind = proxy.get(variable(&self.names.proxy(id)));
+        }
+    }
+
+    fn assignment(&mut self, resolution: Resolution, expr: &mut Expression) {
+        if let Resolution::Local(id) = resolution
+            && self.proxies.contains_key(&id)
+        {
+            expr.kind = ExpressionKind::Variable(self.names.proxy(id));
+        }
+    }
+}
+
+/// Rewrites the declarations of and assignments to proxied locals.
+struct Rewriter<'a> {
+    proxies: &'a HashMap<BindingId, Proxy>,
+    names: &'a Names,
+}
+
+impl Rewriter<'_> {
+    /// The proxy assigned to by `target`, if any.
+    fn proxy(&self, target: &Expression) -> Option<(BindingId, &Proxy)> {
+        let ExpressionKind::Variable(name) = &target.kind else {
+            return None;
+        };
+        let id = self.names.binding(name)?;
+        Some((id, &self.proxies[&id]))
+    }
+
+    /// `setmetatable({[key] = value}, {getter = ..., setter = ...})`
+    fn create(&self, proxy: &Proxy, value: Option<Expression>) -> Expression {
+        let fields = value
+            .map(|value| TableField::Keyed { key: proxy.key.clone(), value })
+            .into_iter()
+            .collect();
+        let rawget = call(
+            variable(&self.names.rawget),
+            vec![variable("proxy"), proxy.key.clone()],
+        );
+        let get = function(&["proxy", "key"], vec![StatementKind::Return(vec![rawget.into()])]);
+        let rawset = call(
+            variable(&self.names.rawset),
+            vec![variable("proxy"), proxy.key.clone(), variable("value")],
+        );
+        let set_params: &[&str] =
+            if proxy.setter.1.is_empty() { &["proxy", "key", "value"] } else { &["proxy", "value"] };
+        let set = function(set_params, vec![StatementKind::Expression(rawset.into())]);
+        let metatable = ExpressionKind::Table(vec![
+            TableField::Named { name: proxy.getter.0.to_string(), value: get },
+            TableField::Named { name: proxy.setter.0.to_string(), value: set },
+        ]);
+        call(
+            variable(&self.names.setmetatable),
+            vec![ExpressionKind::Table(fields).into(), metatable.into()],
+        )
+        .into()
+    }
+
+    /// Statement storing `value` in the proxy of `id`.
+    fn set(&self, id: BindingId, proxy: &Proxy, value: Expression) -> Statement {
+        let target = variable(&self.names.proxy(id));
+        match proxy.setter.1 {
+            "" => StatementKind::Assignment {
+                targets: vec![ExpressionKind::Index {
+                    object: Box::new(target),
+                    key: Box::new(proxy.set_literal.clone()),
+                }
+                .into()],
+                exprs: vec![value],
+            },
+            op => {
+                // Only calls can be statements, so the result is discarded
+                // into a local
+                let write = ExpressionKind::BinaryOp {
+                    left: Box::new(target),
+                    op: op.to_string(),
+                    right: Box::new(value),
+                };
+                let discard = StatementKind::LocalAssignment {
+                    names: vec![self.names.discard.clone()],
+                    exprs: vec![write.into()],
+                };
+                StatementKind::Do(Block::new(vec![discard.into()]))
+            }
+        }
+        .into()
+    }
+
+    /// Append `stmt` to `out`, rewritten if it declares or assigns proxies.
+    fn rewrite(&self, stmt: Statement, out: &mut Vec<Statement>) {
+        match stmt.kind {
+            StatementKind::LocalAssignment { names, mut exprs }
+                if names.iter().any(|name| self.names.binding(name).is_some()) =>
+            {
+                if let ([name], [] | [_]) = (names.as_slice(), exprs.as_slice()) {
+                    let proxy = &self.proxies[&self.names.binding(name).unwrap()];
+                    let value = self.create(proxy, exprs.pop());
+                    out.push(
+                        StatementKind::LocalAssignment { names, exprs: vec![value] }.into(),
+                    );
+                    return;
+                }
+                out.push(StatementKind::LocalAssignment { names: names.clone(), exprs }.into());
+                for (i, name) in names.iter().enumerate() {
+                    // Only the last of several locals with the same name is visible
+                    if let Some(id) = self.names.binding(name)
+                        && !names[i + 1..].contains(name)
+                    {
+                        let value = self.create(&self.proxies[&id], Some(variable(name)));
+                        let assign = StatementKind::Assignment {
+                            targets: vec![variable(name)],
+                            exprs: vec![value],
+                        };
+                        out.push(assign.into());
+                    }
+                }
+            }
+            StatementKind::LocalFunction { name, body } if self.names.binding(&name).is_some() => {
+                let id = self.names.binding(&name).unwrap();
+                let proxy = &self.proxies[&id];
+                let value = self.create(proxy, None);
+                out.push(StatementKind::LocalAssignment { names: vec![name], exprs: vec![value] }.into());
+                out.push(self.set(id, proxy, ExpressionKind::Function(body).into()));
+            }
+            StatementKind::FunctionDeclaration { target, method, mut body }
+                if self.proxy(&target).is_some() || !is_name_path(&target) =>
+            {
+                let target = match method {
+                    Some(method) => {
+                        body.params.insert(0, "self".to_string());
+                        // `function x:m()` reads `x`
+                        let object = match self.proxy(&target) {
+                            Some((id, proxy)) => proxy.get(variable(&self.names.proxy(id))).into(),
+                            None => target,
+                        };
+                        ExpressionKind::Index {
+                            object: Box::new(object),
+                            key: Box::new(ExpressionKind::String(method).into()),
+                        }
+                        .into()
+                    }
+                    None => target,
+                };
+                let function = ExpressionKind::Function(body).into();
+                let assign = StatementKind::Assignment { targets: vec![target], exprs: vec![function] };
+                self.rewrite(assign.into(), out);
+            }
+            StatementKind::Assignment { targets, mut exprs }
+                if targets.iter().any(|target| self.proxy(target).is_some()) =>
+            {
+                if let ([target], [_]) = (targets.as_slice(), exprs.as_slice()) {
+                    let (id, proxy) = self.proxy(target).unwrap();
+                    out.push(self.set(id, proxy, exprs.pop().unwrap()));
+                    return;
+                }
+                // Evaluate all values first, then assign the plain targets
+                // before any proxy so that their keys see the old values
+                let values: Vec<String> =
+                    (0..targets.len()).map(|i| format!("{}{i}", self.names.value_prefix)).collect();
+                let mut statements =
+                    vec![StatementKind::LocalAssignment { names: values.clone(), exprs }.into()];
+                let (mut plain_targets, mut plain_values) = (Vec::new(), Vec::new());
+                let mut setters = Vec::new();
+                for (target, value) in targets.into_iter().zip(&values) {
+                    match self.proxy(&target) {
+                        Some((id, proxy)) => setters.push(self.set(id, proxy, variable(value))),
+                        None => {
+                            plain_targets.push(target);
+                            plain_values.push(variable(value));
+                        }
+                    }
+                }
+                if !plain_targets.is_empty() {
+                    statements.push(
+                        StatementKind::Assignment { targets: plain_targets, exprs: plain_values }
+                            .into(),
+                    );
+                }
+                statements.extend(setters);
+                out.push(StatementKind::Do(Block::new(statements)).into());
+            }
+            kind => out.push(Statement { kind, ..stmt }),
+        }
+    }
+}
+
+impl VisitorMut for Rewriter<'_> {
+    fn visit_block(&mut self, block: &mut Block) {
+        for mut stmt in std::mem::take(&mut block.statements) {
+            self.visit_statement(&mut stmt);
+            self.rewrite(stmt, &mut block.statements);
+        }
+    }
+}
+
+/// Whether `expr` is a valid target of `function a.b.c() end`.
+fn is_name_path(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Variable(_) => true,
+        ExpressionKind::Index { object, key } => {
+            matches!(key.kind, ExpressionKind::String(_)) && is_name_path(object)
+        }
+        _ => false,
+    }
+}
+
+fn variable(name: &str) -> Expression {
+    ExpressionKind::Variable(name.to_string()).into()
+}
+
+fn call(func: Expression, args: Vec<Expression>) -> ExpressionKind {
+    ExpressionKind::Call { func: Box::new(func), args }
+}
+
+fn function(params: &[&str], statements: Vec<StatementKind>) -> Expression {
+    let params = params.iter().map(|param| param.to_string()).collect();
+    let block = Block::new(statements.into_iter().map(Statement::from).collect());
+    ExpressionKind::Function(FunctionBody::new(params, false, block)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn getter_and_setter_differ() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for literal_type in ["dictionary", "number", "string", "any"] {
+            for _ in 0..100 {
+                let proxy = Proxy::new(literal_type, &mut rng);
+                assert_ne!(proxy.getter.0, proxy.setter.0);
+                for literal in [&proxy.key, &proxy.get_literal, &proxy.set_literal] {
+                    let is_number = matches!(literal.kind, ExpressionKind::Number(_));
+                    match literal_type {
+                        "number" => assert!(is_number),
+                        "dictionary" | "string" => assert!(!is_number),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Transformation behind the [`SplitStrings`] step.
+//!
+//! String literals are cut into chunks of random length which are joined
+//! again at runtime, either with `..`, with `table.concat` or with a custom
+//! function that receives the chunks in shuffled order followed by the
+//! positions needed to restore them.
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::ast::{
+    AstNode, Expression, ExpressionKind, FunctionBody, Statement, StatementKind, TableField,
+};
+use crate::config::IDENT_PREFIX;
+use crate::parser::parse_snippet;
+use crate::pipeline::Pipeline;
+use crate::visitor::{visit_ast, walk_expression, walk_function_body, VisitorMut};
+
+use super::{unused_suffix, SplitStrings};
+
+/// Custom concatenation function. Its argument holds the positions of the
+/// chunks followed by the table of shuffled chunks.
+const CUSTOM_CONCAT: &str = r#"
+return function(t)
+	local chunks, result = t[#t], ""
+	for i = 1, #chunks do
+		result = result .. chunks[t[i]]
+	end
+	return result
+end
+"#;
+
+/// How the chunks are joined, resolved from the step's settings.
+enum Joiner {
+    /// `a .. b .. c`
+    Strcat,
+    /// `concat({a, b, c})`, `concat` being a local bound to `table.concat`.
+    Table,
+    /// One custom function for the whole script.
+    Global,
+    /// Custom functions declared in every function that needs them.
+    Local,
+    /// The custom function is repeated at every use.
+    Inline,
+}
+
+pub(super) fn apply(step: &SplitStrings, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    let joiner = match (step.concatenation_type.as_str(), step.custom_function_type.as_str()) {
+        ("strcat", _) => Joiner::Strcat,
+        ("table", _) => Joiner::Table,
+        (_, "local") => Joiner::Local,
+        (_, "inline") => Joiner::Inline,
+        _ => Joiner::Global,
+    };
+    let name = format!("{IDENT_PREFIX}concat{}", unused_suffix(pipeline, "concat"));
+    let mut splitter = Splitter {
+        step,
+        rng: pipeline.rng("SplitStrings"),
+        joiner,
+        name: &name,
+        split: false,
+        used: vec![Vec::new()],
+    };
+    visit_ast(&mut splitter, &mut ast);
+    if !splitter.split {
+        return ast;
+    }
+
+    let declarations = match splitter.joiner {
+        Joiner::Strcat | Joiner::Inline => Vec::new(),
+        Joiner::Table => {
+            let concat = ExpressionKind::Index {
+                object: Box::new(ExpressionKind::Variable("table".to_string()).into()),
+                key: Box::new(ExpressionKind::String("concat".to_string()).into()),
+            };
+            vec![(name.clone(), concat.into())]
+        }
+        Joiner::Global => vec![(name.clone(), custom_concat())],
+        Joiner::Local => splitter.local_declarations(),
+    };
+    declare(&mut ast.block.statements, declarations);
+    ast
+}
+
+/// Insert `local name = value` for every declaration at the start of a block.
+fn declare(statements: &mut Vec<Statement>, declarations: Vec<(String, Expression)>) {
+    let declarations = declarations.into_iter().map(|(name, value)| {
+        Statement::from(StatementKind::LocalAssignment { names: vec![name], exprs: vec![value] })
+    });
+    statements.splice(0..0, declarations);
+}
+
+fn custom_concat() -> Expression {
+    match parse_snippet(CUSTOM_CONCAT).statements.pop().map(|stmt| stmt.kind) {
+        Some(StatementKind::Return(mut exprs)) => exprs.pop().unwrap(),
+        _ => unreachable!("snippet returns the function"),
+    }
+}
+
+struct Splitter<'a, R> {
+    step: &'a SplitStrings,
+    rng: R,
+    joiner: Joiner,
+    name: &'a str,
+    /// Whether any string was split.
+    split: bool,
+    /// For [`Joiner::Local`], the local functions used by each enclosing
+    /// function, the main chunk first.
+    used: Vec<Vec<usize>>,
+}
+
+impl<R: Rng> Splitter<'_, R> {
+    fn chunks(&mut self, s: &str) -> Vec<String> {
+        let chars: Vec<char> = s.chars().collect();
+        let min = self.step.min_length.max(1) as usize;
+        let max = (self.step.max_length as usize).max(min);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let end = (start + self.rng.gen_range(min..=max)).min(chars.len());
+            chunks.push(chars[start..end].iter().collect());
+            start = end;
+        }
+        chunks
+    }
+
+    fn join(&mut self, chunks: Vec<String>) -> ExpressionKind {
+        let strings =
+            |chunks: Vec<String>| chunks.into_iter().map(|s| ExpressionKind::String(s).into());
+        match self.joiner {
+            Joiner::Strcat => strcat(&strings(chunks).collect::<Vec<_>>()),
+            Joiner::Table => call(variable(self.name), table(strings(chunks).collect())),
+            Joiner::Global | Joiner::Local | Joiner::Inline => {
+                let mut order: Vec<usize> = (0..chunks.len()).collect();
+                order.shuffle(&mut self.rng);
+                // The chunk at `order[i]` of the shuffled table is the `i`th
+                let mut shuffled = vec![String::new(); chunks.len()];
+                for (chunk, &position) in chunks.into_iter().zip(&order) {
+                    shuffled[position] = chunk;
+                }
+                let mut fields: Vec<Expression> = order
+                    .iter()
+                    .map(|&position| ExpressionKind::Number(position as f64 + 1.0).into())
+                    .collect();
+                fields.push(table(strings(shuffled).collect()));
+                call(self.custom_function(), table(fields))
+            }
+        }
+    }
+
+    fn custom_function(&mut self) -> Expression {
+        match self.joiner {
+            Joiner::Local => {
+                let count = self.step.custom_local_functions_count.max(1) as usize;
+                let index = self.rng.gen_range(0..count);
+                let used = self.used.last_mut().unwrap();
+                if !used.contains(&index) {
+                    used.push(index);
+                }
+                variable(&self.local_name(index))
+            }
+            Joiner::Inline => ExpressionKind::Paren(Box::new(custom_concat())).into(),
+            _ => variable(self.name),
+        }
+    }
+
+    fn local_name(&self, index: usize) -> String {
+        format!("{}_{index}", self.name)
+    }
+
+    /// Declarations of the local functions used by the innermost function.
+    fn local_declarations(&mut self) -> Vec<(String, Expression)> {
+        let mut used = self.used.pop().unwrap();
+        used.sort_unstable();
+        used.into_iter().map(|index| (self.local_name(index), custom_concat())).collect()
+    }
+}
+
+impl<R: Rng> VisitorMut for Splitter<'_, R> {
+    fn visit_function_body(&mut self, body: &mut FunctionBody) {
+        self.used.push(Vec::new());
+        walk_function_body(self, body);
+        let declarations = self.local_declarations();
+        declare(&mut body.block.statements, declarations);
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        let ExpressionKind::String(s) = &expr.kind else {
+            return walk_expression(self, expr);
+        };
+        if self.rng.r#gen::<f64>() > self.step.treshold {
+            return;
+        }
+        let chunks = self.chunks(s);
+        if chunks.len() > 1 {
+            self.split = true;
+            expr.kind = self.join(chunks);
+        }
+    }
+}
+
+/// Joins the chunks as a balanced tree, so that long strings do not exceed
+/// the nesting limit of the Lua parser.
+fn strcat(chunks: &[Expression]) -> ExpressionKind {
+    if let [chunk] = chunks {
+        return chunk.kind.clone();
+    }
+    let (left, right) = chunks.split_at(chunks.len() / 2);
+    ExpressionKind::BinaryOp {
+        left: Box::new(strcat(left).into()),
+        op: "..".to_string(),
+        right: Box::new(strcat(right).into()),
+    }
+}
+
+fn variable(name: &str) -> Expression {
+    ExpressionKind::Variable(name.to_string()).into()
+}
+
+fn table(values: Vec<Expression>) -> Expression {
+    ExpressionKind::Table(values.into_iter().map(TableField::Value).collect()).into()
+}
+
+fn call(func: Expression, arg: Expression) -> ExpressionKind {
+    ExpressionKind::Call { func: Box::new(func), args: vec![arg] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strcat_is_balanced() {
+        let chunks: Vec<Expression> =
+            (0..4).map(|i| ExpressionKind::String(i.to_string()).into()).collect();
+        let ExpressionKind::BinaryOp { left, right, .. } = strcat(&chunks) else {
+            panic!("expected a concatenation");
+        };
+        assert!(matches!(left.kind, ExpressionKind::BinaryOp { .. }));
+        assert!(matches!(right.kind, ExpressionKind::BinaryOp { .. }));
+    }
+}
@@ -0,0 +1,93 @@
+//! Transformations behind the [`Watermark`] and [`WatermarkCheck`] steps.
+//!
+//! The watermark is stored in a global when the script starts:
+//! `("content"):gsub(".+", function(x) VARIABLE = x end)`. The check stops
+//! the script unless that global holds the expected content. It stores the
+//! watermark in a global with a random name and defers a [`Watermark`] step
+//! setting it to the end of the pipeline, so that the watermark stays
+//! readable while the check is obfuscated by the steps that follow it.
+
+use rand::Rng;
+
+use crate::ast::{AstNode, Block, ExpressionKind, FunctionBody, StatementKind};
+use crate::config::IDENT_PREFIX;
+use crate::error::PrometheusError;
+use crate::lua::LuaVersion;
+use crate::name_generators::mangled_name;
+use crate::pipeline::Pipeline;
+
+use super::{Watermark, WatermarkCheck};
+
+pub(super) fn apply(
+    step: &Watermark,
+    mut ast: AstNode,
+    pipeline: &Pipeline,
+) -> Result<AstNode, PrometheusError> {
+    if step.content.is_empty() {
+        return Ok(ast);
+    }
+    if !is_valid_name(&step.custom_variable, pipeline.lua_version) {
+        return Err(PrometheusError::step(
+            "Watermark",
+            format!("CustomVariable {:?} is not a valid Lua name", step.custom_variable),
+        ));
+    }
+    let param = format!("{IDENT_PREFIX}watermark");
+    let assign = StatementKind::Assignment {
+        targets: vec![ExpressionKind::Variable(step.custom_variable.clone()).into()],
+        exprs: vec![ExpressionKind::Variable(param.clone()).into()],
+    };
+    let setter = FunctionBody::new(vec![param], false, Block::new(vec![assign.into()]));
+    let gsub = ExpressionKind::MethodCall {
+        object: Box::new(
+            ExpressionKind::Paren(Box::new(ExpressionKind::String(step.content.clone()).into()))
+                .into(),
+        ),
+        method: "gsub".to_string(),
+        args: vec![
+            ExpressionKind::String(".+".to_string()).into(),
+            ExpressionKind::Function(setter).into(),
+        ],
+    };
+    ast.block.statements.insert(0, StatementKind::Expression(gsub.into()).into());
+    Ok(ast)
+}
+
+pub(super) fn apply_check(step: &WatermarkCheck, mut ast: AstNode, pipeline: &Pipeline) -> AstNode {
+    if step.content.is_empty() {
+        return ast;
+    }
+    let mut rng = pipeline.rng("WatermarkCheck");
+    let variable = loop {
+        let name = format!("_{}", mangled_name(rng.gen_range(10_000_000_000..100_000_000_000)));
+        if !pipeline.scope().globals.contains(&name) {
+            break name;
+        }
+    };
+    pipeline.defer_step(Box::new(Watermark {
+        content: step.content.clone(),
+        custom_variable: variable.clone(),
+    }));
+
+    let check = StatementKind::If {
+        clauses: vec![(
+            ExpressionKind::BinaryOp {
+                left: Box::new(ExpressionKind::Variable(variable).into()),
+                op: "~=".to_string(),
+                right: Box::new(ExpressionKind::String(step.content.clone()).into()),
+            }
+            .into(),
+            Block::new(vec![StatementKind::Return(Vec::new()).into()]),
+        )],
+        else_block: None,
+    };
+    ast.block.statements.insert(0, check.into());
+    ast
+}
+
+fn is_valid_name(name: &str, version: LuaVersion) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !version.conventions().keywords.contains(&name)
+}
@@ -0,0 +1,585 @@
+//! Turns an [`AstNode`] back into Lua source code.
+
+use crate::ast::{
+    binary_priority, AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement,
+    StatementKind, TableField, UNARY_PRIORITY,
+};
+use crate::config;
+use crate::lua::LuaVersion;
+use crate::util::escape;
+
+/// Emits Lua source for an AST.
+///
+/// In pretty mode every statement is placed on its own line and nested blocks
+/// are indented. Otherwise only the whitespace required to keep adjacent
+/// tokens apart is written.
+pub struct Unparser {
+    version: LuaVersion,
+    pretty: bool,
+    out: String,
+    indent: usize,
+}
+
+impl Unparser {
+    pub fn new(version: LuaVersion, pretty: bool) -> Self {
+        Self { version, pretty, out: String::new(), indent: 0 }
+    }
+
+    /// Convert the AST into Lua source code.
+    pub fn unparse(mut self, ast: &AstNode) -> String {
+        self.block(&ast.block);
+        if self.pretty && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    /// Append a token, inserting a space if it would otherwise merge with the
+    /// previous one.
+    fn token(&mut self, token: &str) {
+        if let (Some(last), Some(first)) = (self.out.chars().last(), token.chars().next())
+            && needs_separator(last, first)
+        {
+            self.out.push(' ');
+        }
+        self.out.push_str(token);
+    }
+
+    /// Space that is only written in pretty mode.
+    fn space(&mut self) {
+        if self.pretty {
+            self.out.push_str(config::SPACE);
+        }
+    }
+
+    fn newline(&mut self) {
+        if self.pretty {
+            self.out.push('\n');
+            for _ in 0..self.indent {
+                self.out.push_str(config::TAB);
+            }
+        }
+    }
+
+    fn is_keyword(&self, name: &str) -> bool {
+        self.version.conventions().keywords.contains(&name)
+    }
+
+    /// Whether `name` can be written as a bare identifier.
+    fn is_valid_name(&self, name: &str) -> bool {
+        let mut chars = name.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !self.is_keyword(name)
+    }
+
+    fn block(&mut self, block: &Block) {
+        for (i, stmt) in block.statements.iter().enumerate() {
+            if i > 0 || !self.out.is_empty() {
+                self.newline();
+            }
+            if i > 0 && statement_starts_with_paren(stmt) {
+                // Without a separator `a = b (f)()` would be read as a call of `b`.
+                self.token(";");
+            }
+            self.statement(stmt);
+        }
+    }
+
+    /// Write a nested block followed by its closing keyword.
+    fn body(&mut self, block: &Block, close: &str) {
+        self.indent += 1;
+        self.block(block);
+        self.indent -= 1;
+        if !block.statements.is_empty() {
+            self.newline();
+        } else {
+            self.space();
+        }
+        self.token(close);
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::LocalAssignment { names, exprs } => {
+                self.token("local");
+                self.space();
+                self.name_list(names);
+                if !exprs.is_empty() {
+                    self.assign_op();
+                    self.expression_list(exprs);
+                }
+            }
+            StatementKind::LocalFunction { name, body } => {
+                self.token("local");
+                self.space();
+                self.token("function");
+                self.space();
+                self.token(name);
+                self.function_body(body, false);
+            }
+            StatementKind::FunctionDeclaration { target, method, body } => {
+                if self.is_function_name(target) {
+                    self.token("function");
+                    self.space();
+                    self.expression(target);
+                    if let Some(method) = method {
+                        self.token(":");
+                        self.token(method);
+                    }
+                    self.function_body(body, false);
+                } else if let Some(method) = method {
+                    // Fall back to `target.method = function(self, ...)`.
+                    let key = ExpressionKind::String(method.clone()).into();
+                    self.index(target, &key);
+                    self.assign_op();
+                    self.token("function");
+                    self.function_body(body, true);
+                } else {
+                    self.expression(target);
+                    self.assign_op();
+                    self.token("function");
+                    self.function_body(body, false);
+                }
+            }
+            StatementKind::Assignment { targets, exprs } => {
+                self.expression_list(targets);
+                self.assign_op();
+                self.expression_list(exprs);
+            }
+            StatementKind::Do(block) => {
+                self.token("do");
+                self.body(block, "end");
+            }
+            StatementKind::While { condition, block } => {
+                self.token("while");
+                self.space();
+                self.expression(condition);
+                self.space();
+                self.token("do");
+                self.body(block, "end");
+            }
+            StatementKind::Repeat { block, condition } => {
+                self.token("repeat");
+                self.body(block, "until");
+                self.space();
+                self.expression(condition);
+            }
+            StatementKind::If { clauses, else_block } => {
+                for (i, (condition, block)) in clauses.iter().enumerate() {
+                    self.token(if i == 0 { "if" } else { "elseif" });
+                    self.space();
+                    self.expression(condition);
+                    self.space();
+                    self.token("then");
+                    self.indent += 1;
+                    self.block(block);
+                    self.indent -= 1;
+                    self.newline();
+                }
+                if let Some(block) = else_block {
+                    self.token("else");
+                    self.indent += 1;
+                    self.block(block);
+                    self.indent -= 1;
+                    self.newline();
+                }
+                self.token("end");
+            }
+            StatementKind::NumericFor { var, start, limit, step, block } => {
+                self.token("for");
+                self.space();
+                self.token(var);
+                self.assign_op();
+                self.expression(start);
+                self.comma();
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.comma();
+                    self.expression(step);
+                }
+                self.space();
+                self.token("do");
+                self.body(block, "end");
+            }
+            StatementKind::GenericFor { names, exprs, block } => {
+                self.token("for");
+                self.space();
+                self.name_list(names);
+                self.space();
+                self.token("in");
+                self.space();
+                self.expression_list(exprs);
+                self.space();
+                self.token("do");
+                self.body(block, "end");
+            }
+            StatementKind::Return(exprs) => {
+                self.token("return");
+                if !exprs.is_empty() {
+                    self.space();
+                    self.expression_list(exprs);
+                }
+            }
+            StatementKind::Break => self.token("break"),
+            StatementKind::Continue => self.token("continue"),
+            StatementKind::Expression(expr) => self.expression(expr),
+        }
+    }
+
+    fn assign_op(&mut self) {
+        self.space();
+        self.token("=");
+        self.space();
+    }
+
+    fn comma(&mut self) {
+        self.token(",");
+        self.space();
+    }
+
+    fn name_list(&mut self, names: &[String]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.comma();
+            }
+            self.token(name);
+        }
+    }
+
+    fn expression_list(&mut self, exprs: &[Expression]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.comma();
+            }
+            self.expression(expr);
+        }
+    }
+
+    /// Whether `target` can be written after `function` as `a.b.c`.
+    fn is_function_name(&self, target: &Expression) -> bool {
+        match &target.kind {
+            ExpressionKind::Variable(name) => self.is_valid_name(name),
+            ExpressionKind::Index { object, key } => {
+                matches!(&key.kind, ExpressionKind::String(k) if self.is_valid_name(k))
+                    && self.is_function_name(object)
+            }
+            _ => false,
+        }
+    }
+
+    fn function_body(&mut self, body: &FunctionBody, add_self: bool) {
+        self.token("(");
+        let mut first = true;
+        if add_self {
+            self.token("self");
+            first = false;
+        }
+        for param in &body.params {
+            if !first {
+                self.comma();
+            }
+            self.token(param);
+            first = false;
+        }
+        if body.is_vararg {
+            if !first {
+                self.comma();
+            }
+            self.token("...");
+        }
+        self.token(")");
+        self.body(&body.block, "end");
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        self.expression_with_limit(expr, 0);
+    }
+
+    /// Write `expr` as the right operand of an operator whose binding power is
+    /// `limit`, adding parentheses if the parser would otherwise regroup it.
+    /// Unary expressions never need parentheses in this position.
+    fn expression_with_limit(&mut self, expr: &Expression, limit: u8) {
+        let priority = match &expr.kind {
+            ExpressionKind::BinaryOp { op, .. } => binary_priority(op).map(|(left, _)| left),
+            _ => None,
+        };
+        if priority.is_some_and(|p| p <= limit) {
+            self.token("(");
+            self.expression(expr);
+            self.token(")");
+            return;
+        }
+
+        match &expr.kind {
+            ExpressionKind::Nil => self.token("nil"),
+            ExpressionKind::Boolean(b) => self.token(if *b { "true" } else { "false" }),
+            ExpressionKind::Number(n) => self.token(&format_number(*n)),
+            ExpressionKind::String(s) => self.token(&format!("\"{}\"", escape(s))),
+            ExpressionKind::Vararg => self.token("..."),
+            ExpressionKind::Variable(name) => self.token(name),
+            ExpressionKind::Function(body) => {
+                self.token("function");
+                self.function_body(body, false);
+            }
+            ExpressionKind::Table(fields) => self.table(fields),
+            ExpressionKind::BinaryOp { left, op, right } => {
+                let (left_priority, right_priority) = binary_priority(op).unwrap();
+                self.left_operand(left, left_priority);
+                self.space();
+                self.token(op);
+                self.space();
+                self.expression_with_limit(right, right_priority);
+            }
+            ExpressionKind::UnaryOp { op, operand } => {
+                self.token(op);
+                if op == "not" {
+                    self.space();
+                }
+                self.expression_with_limit(operand, UNARY_PRIORITY);
+            }
+            ExpressionKind::Paren(inner) => {
+                self.token("(");
+                self.expression(inner);
+                self.token(")");
+            }
+            ExpressionKind::Index { object, key } => self.index(object, key),
+            ExpressionKind::Call { func, args } => {
+                self.prefix(func);
+                self.arguments(args);
+            }
+            ExpressionKind::MethodCall { object, method, args } => {
+                self.prefix(object);
+                self.token(":");
+                self.token(method);
+                self.arguments(args);
+            }
+        }
+    }
+
+    /// Write the left operand of a binary operator. An operand binds to the
+    /// operator unless its own right priority is lower than the operator's
+    /// left priority.
+    fn left_operand(&mut self, expr: &Expression, op_left_priority: u8) {
+        let right_priority = match &expr.kind {
+            ExpressionKind::BinaryOp { op, .. } => binary_priority(op).map(|(_, right)| right),
+            ExpressionKind::UnaryOp { .. } => Some(UNARY_PRIORITY),
+            ExpressionKind::Number(n) if format_number(*n).starts_with('-') => Some(UNARY_PRIORITY),
+            _ => None,
+        };
+        if right_priority.is_some_and(|p| p < op_left_priority) {
+            self.token("(");
+            self.expression(expr);
+            self.token(")");
+        } else {
+            self.expression(expr);
+        }
+    }
+
+    /// Write an expression used as the prefix of a call or index.
+    fn prefix(&mut self, expr: &Expression) {
+        if is_prefix_expression(expr) {
+            self.expression(expr);
+        } else {
+            self.token("(");
+            self.expression(expr);
+            self.token(")");
+        }
+    }
+
+    fn index(&mut self, object: &Expression, key: &Expression) {
+        self.prefix(object);
+        match &key.kind {
+            ExpressionKind::String(name) if self.is_valid_name(name) => {
+                self.token(".");
+                self.token(name);
+            }
+            _ => {
+                self.token("[");
+                self.expression(key);
+                self.token("]");
+            }
+        }
+    }
+
+    fn arguments(&mut self, args: &[Expression]) {
+        self.token("(");
+        self.expression_list(args);
+        self.token(")");
+    }
+
+    fn table(&mut self, fields: &[TableField]) {
+        self.token("{");
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.comma();
+            }
+            match field {
+                TableField::Value(value) => self.expression(value),
+                TableField::Named { name, value } => {
+                    if self.is_valid_name(name) {
+                        self.token(name);
+                    } else {
+                        self.token("[");
+                        self.expression(&ExpressionKind::String(name.clone()).into());
+                        self.token("]");
+                    }
+                    self.assign_op();
+                    self.expression(value);
+                }
+                TableField::Keyed { key, value } => {
+                    self.token("[");
+                    self.expression(key);
+                    self.token("]");
+                    self.assign_op();
+                    self.expression(value);
+                }
+            }
+        }
+        self.token("}");
+    }
+}
+
+/// Convert the AST into Lua source code for `version`.
+pub fn unparse(ast: &AstNode, version: LuaVersion, pretty: bool) -> String {
+    Unparser::new(version, pretty).unparse(ast)
+}
+
+/// Whether two characters would lex as a single token when written adjacently.
+fn needs_separator(last: char, first: char) -> bool {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    (is_word(last) && is_word(first))
+        || (last == '-' && first == '-')
+        || (last == '.' && (first == '.' || first.is_ascii_digit()))
+        || (last.is_ascii_digit() && first == '.')
+        || (last == '[' && (first == '[' || first == '='))
+        || (matches!(last, '=' | '<' | '>' | '~') && first == '=')
+}
+
+/// Expressions that can be called or indexed without parentheses.
+fn is_prefix_expression(expr: &Expression) -> bool {
+    matches!(
+        expr.kind,
+        ExpressionKind::Variable(_)
+            | ExpressionKind::Index { .. }
+            | ExpressionKind::Call { .. }
+            | ExpressionKind::MethodCall { .. }
+            | ExpressionKind::Paren(_)
+    )
+}
+
+/// Whether the emitted statement would begin with `(`.
+fn statement_starts_with_paren(stmt: &Statement) -> bool {
+    fn leftmost(expr: &Expression) -> bool {
+        match &expr.kind {
+            ExpressionKind::Paren(_) => true,
+            ExpressionKind::Index { object, .. } | ExpressionKind::MethodCall { object, .. } => {
+                !is_prefix_expression(object) || leftmost(object)
+            }
+            ExpressionKind::Call { func, .. } => !is_prefix_expression(func) || leftmost(func),
+            _ => false,
+        }
+    }
+    match &stmt.kind {
+        StatementKind::Assignment { targets, .. } => targets.first().is_some_and(leftmost),
+        StatementKind::Expression(expr) => leftmost(expr),
+        _ => false,
+    }
+}
+
+/// Format a number so that it reads back as the same value.
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "(0/0)".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "(1/0)" } else { "(-1/0)" }.to_string()
+    } else if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 {
+        format!("{}", n as i64)
+    } else {
+        format!("{n}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn roundtrip(src: &str, pretty: bool) -> String {
+        let tokens = tokenize(src, LuaVersion::Lua51).unwrap();
+        let ast = parse(&tokens, LuaVersion::Lua51).unwrap().ast;
+        let out = unparse(&ast, LuaVersion::Lua51, pretty);
+        // The output must parse back into the same tree.
+        let reparsed = parse(&tokenize(&out, LuaVersion::Lua51).unwrap(), LuaVersion::Lua51)
+            .unwrap_or_else(|e| panic!("failed to reparse {out:?}: {e:?}"))
+            .ast;
+        assert_eq!(ast, reparsed, "output was {out:?}");
+        out
+    }
+
+    #[test]
+    fn compact_output() {
+        assert_eq!(roundtrip("local a = 1", false), "local a=1");
+        assert_eq!(
+            roundtrip("local function f(a, ...) return a and -a end", false),
+            "local function f(a,...)return a and-a end"
+        );
+        assert_eq!(roundtrip("x = a - -b", false), "x=a- -b");
+    }
+
+    #[test]
+    fn pretty_output() {
+        assert_eq!(
+            roundtrip("if a then b() else c() end", true),
+            "if a then\n\tb()\nelse\n\tc()\nend\n"
+        );
+    }
+
+    #[test]
+    fn keeps_precedence() {
+        roundtrip("x = (a + b) * c ^ -d .. e .. (f .. g)", false);
+        roundtrip("x = -(a ^ b) + (-a) ^ b - (a - b)", false);
+        roundtrip("x = not (a == b) or #t > 1 and (c or d)", false);
+    }
+
+    #[test]
+    fn inserts_parens_for_generated_trees() {
+        let number = |n: f64| Box::new(Expression::from(ExpressionKind::Number(n)));
+        let sum = ExpressionKind::BinaryOp { left: number(1.0), op: "+".into(), right: number(2.0) };
+        let expr = ExpressionKind::BinaryOp {
+            left: Box::new(sum.into()),
+            op: "*".into(),
+            right: number(-3.0),
+        };
+        let ast = AstNode::new(Block::new(vec![StatementKind::Return(vec![expr.into()]).into()]));
+        assert_eq!(unparse(&ast, LuaVersion::Lua51, false), "return(1+2)*-3");
+    }
+
+    #[test]
+    fn separates_ambiguous_calls() {
+        assert_eq!(roundtrip("a = b; (f)()", false), "a=b;(f)()");
+    }
+
+    #[test]
+    fn escapes_strings_and_indexes() {
+        assert_eq!(
+            roundtrip("t[\"a b\"] = t.c .. 'd\\n' .. t['end']", false),
+            "t[\"a b\"]=t.c..\"d\\n\"..t[\"end\"]"
+        );
+    }
+
+    #[test]
+    fn full_programs() {
+        roundtrip(
+            "local arr = {}\nfor i = 1, 100 do local x; x = (x or 1) + i; arr[i] = function() return x; end end \
+             for i, func in ipairs(arr) do print(func()) end",
+            false,
+        );
+        roundtrip(
+            "local function fibonacci(max) local a, b = 0, 1 while a < max do print(a) a, b = b, a + b end end",
+            true,
+        );
+        roundtrip("function a.b.c:d(...) return ('x'):rep(2), {...}, #... end", false);
+    }
+}
@@ -31,6 +31,12 @@ pub fn escape(input: &str) -> String {
             '\u{0B}' => "\\v".to_string(),
             '\"' => "\\\"".to_string(),
             '\'' => "\\'".to_string(),
+            // Chars above 255 come from `\u{...}` escapes and stand for their UTF-8 bytes
+            c if c as u32 > 0xFF => c
+                .encode_utf8(&mut [0; 4])
+                .bytes()
+                .map(|byte| format!("\\{byte:03}"))
+                .collect(),
             c if !c.is_ascii() || (c.is_control() && c != '\n' && c != '\t') => {
                 format!("\\{:03}", c as u8)
             }
@@ -39,6 +45,20 @@ pub fn escape(input: &str) -> String {
         .collect()
 }
 
+/// Bytes of the Lua string `s`. The lexer stores every byte as a char below
+/// 256; chars above that come from `\u{...}` escapes and stand for their
+/// UTF-8 bytes.
+pub fn lua_bytes(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        match u8::try_from(c) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
 /// Split a string into a vector of characters.
 pub fn chararray(input: &str) -> Vec<char> {
     input.chars().collect()
@@ -61,6 +81,12 @@ mod tests {
     #[test]
     fn escape_basic() {
         assert_eq!(escape("a\n"), "a\\n");
+        assert_eq!(escape("\u{ff}\u{20ac}"), "\\255\\226\\130\\172");
+    }
+
+    #[test]
+    fn lua_bytes_keeps_escaped_bytes() {
+        assert_eq!(lua_bytes("a\u{ff}\u{20ac}"), [b'a', 0xff, 0xe2, 0x82, 0xac]);
     }
 
     #[test]
@@ -0,0 +1,170 @@
+//! Mutable traversal of the AST for steps that rewrite nodes in place.
+//!
+//! Implementors override the hooks they are interested in and call the
+//! matching `walk_*` function to continue into the children. The target of a
+//! [`StatementKind::FunctionDeclaration`] is a name path rather than a value
+//! and is not visited.
+
+use crate::ast::{
+    AstNode, Block, Expression, ExpressionKind, FunctionBody, Statement, StatementKind,
+    TableField,
+};
+
+pub trait VisitorMut {
+    fn visit_block(&mut self, block: &mut Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, stmt: &mut Statement) {
+        walk_statement(self, stmt);
+    }
+
+    fn visit_function_body(&mut self, body: &mut FunctionBody) {
+        walk_function_body(self, body);
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        walk_expression(self, expr);
+    }
+}
+
+/// Visit every node of `ast`.
+pub fn visit_ast<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut AstNode) {
+    visitor.visit_block(&mut ast.block);
+}
+
+pub fn walk_block<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for stmt in &mut block.statements {
+        visitor.visit_statement(stmt);
+    }
+}
+
+pub fn walk_function_body<V: VisitorMut + ?Sized>(visitor: &mut V, body: &mut FunctionBody) {
+    visitor.visit_block(&mut body.block);
+}
+
+fn walk_expressions<V: VisitorMut + ?Sized>(visitor: &mut V, exprs: &mut [Expression]) {
+    for expr in exprs {
+        visitor.visit_expression(expr);
+    }
+}
+
+pub fn walk_statement<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Statement) {
+    match &mut stmt.kind {
+        StatementKind::LocalAssignment { exprs, .. } => walk_expressions(visitor, exprs),
+        StatementKind::LocalFunction { body, .. }
+        | StatementKind::FunctionDeclaration { body, .. } => visitor.visit_function_body(body),
+        StatementKind::Assignment { targets, exprs } => {
+            walk_expressions(visitor, targets);
+            walk_expressions(visitor, exprs);
+        }
+        StatementKind::Do(block) => visitor.visit_block(block),
+        StatementKind::While { condition, block } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(block);
+        }
+        StatementKind::Repeat { block, condition } => {
+            visitor.visit_block(block);
+            visitor.visit_expression(condition);
+        }
+        StatementKind::If { clauses, else_block } => {
+            for (condition, block) in clauses {
+                visitor.visit_expression(condition);
+                visitor.visit_block(block);
+            }
+            if let Some(block) = else_block {
+                visitor.visit_block(block);
+            }
+        }
+        StatementKind::NumericFor { start, limit, step, block, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(limit);
+            if let Some(step) = step {
+                visitor.visit_expression(step);
+            }
+            visitor.visit_block(block);
+        }
+        StatementKind::GenericFor { exprs, block, .. } => {
+            walk_expressions(visitor, exprs);
+            visitor.visit_block(block);
+        }
+        StatementKind::Return(exprs) => walk_expressions(visitor, exprs),
+        StatementKind::Expression(expr) => visitor.visit_expression(expr),
+        StatementKind::Break | StatementKind::Continue => {}
+    }
+}
+
+pub fn walk_expression<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match &mut expr.kind {
+        ExpressionKind::Function(body) => visitor.visit_function_body(body),
+        ExpressionKind::Table(fields) => {
+            for field in fields {
+                match field {
+                    TableField::Value(value) | TableField::Named { value, .. } => {
+                        visitor.visit_expression(value)
+                    }
+                    TableField::Keyed { key, value } => {
+                        visitor.visit_expression(key);
+                        visitor.visit_expression(value);
+                    }
+                }
+            }
+        }
+        ExpressionKind::BinaryOp { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        ExpressionKind::UnaryOp { operand, .. } => visitor.visit_expression(operand),
+        ExpressionKind::Paren(inner) => visitor.visit_expression(inner),
+        ExpressionKind::Index { object, key } => {
+            visitor.visit_expression(object);
+            visitor.visit_expression(key);
+        }
+        ExpressionKind::Call { func, args } => {
+            visitor.visit_expression(func);
+            walk_expressions(visitor, args);
+        }
+        ExpressionKind::MethodCall { object, args, .. } => {
+            visitor.visit_expression(object);
+            walk_expressions(visitor, args);
+        }
+        ExpressionKind::Nil
+        | ExpressionKind::Boolean(_)
+        | ExpressionKind::Number(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Vararg
+        | ExpressionKind::Variable(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::lua::LuaVersion;
+    use crate::parser::parse;
+
+    struct Strings(Vec<String>);
+
+    impl VisitorMut for Strings {
+        fn visit_expression(&mut self, expr: &mut Expression) {
+            if let ExpressionKind::String(s) = &expr.kind {
+                self.0.push(s.clone());
+            }
+            walk_expression(self, expr);
+        }
+    }
+
+    #[test]
+    fn visits_nested_expressions_in_order() {
+        let tokens = tokenize(
+            r#"local t = {"a", b = "b", ["c"] = "d"} function t.x() return f("e"):g("f") end"#,
+            LuaVersion::Lua51,
+        )
+        .unwrap();
+        let mut ast = parse(&tokens, LuaVersion::Lua51).unwrap().ast;
+        let mut visitor = Strings(Vec::new());
+        visit_ast(&mut visitor, &mut ast);
+        assert_eq!(visitor.0, ["a", "b", "c", "d", "e", "f"]);
+    }
+}
@@ -0,0 +1,15 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn every_function_is_vararg() {
+    let config = common::step_config("AddVararg", "{}", 0);
+    let out = Pipeline::from_config(config).unwrap().apply(programs::METHODS).unwrap();
+    for definition in out.split("function").skip(1) {
+        let params = &definition[..definition.find(')').unwrap()];
+        assert!(params.ends_with("..."), "{out}");
+    }
+}
@@ -0,0 +1,54 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::{Config, Pipeline};
+
+const USE_DEBUG: &str = r#"{ "UseDebug": true }"#;
+const NO_DEBUG: &str = r#"{ "UseDebug": false }"#;
+
+fn assert_tamper_detected(result: mlua::Result<String>) {
+    match result {
+        Err(err) => assert!(err.to_string().contains("Tamper Detected!"), "{err}"),
+        Ok(out) => panic!("tampering was not detected, printed {out:?}"),
+    }
+}
+
+#[test]
+fn portable_checks_run_without_debug_library() {
+    let config = common::step_config("AntiTamper", NO_DEBUG, 1);
+    let out = Pipeline::from_config(config).unwrap().apply(programs::ERRORS).unwrap();
+    assert_eq!(common::try_run_lua(&out, false).unwrap(), "610 false boom 3");
+}
+
+#[test]
+fn checks_survive_vmify() {
+    let mut config = common::step_config("AntiTamper", USE_DEBUG, 1);
+    config.steps.extend(common::step_config("Vmify", "{}", 1).steps);
+    common::assert_equivalent_with(programs::ERRORS, config);
+}
+
+#[test]
+fn beautified_output_is_detected() {
+    let beautify = Config { lua_version: common::LUA_VERSION, pretty_print: true, seed: 1, ..Config::default() };
+    for (settings, use_debug) in [(NO_DEBUG, false), (USE_DEBUG, true)] {
+        let config = common::step_config("AntiTamper", settings, 1);
+        let out = Pipeline::from_config(config).unwrap().apply(programs::ERRORS).unwrap();
+        assert!(common::try_run_lua(&out, use_debug).is_ok());
+        let beautified = Pipeline::from_config(beautify.clone()).unwrap().apply(&out).unwrap();
+        assert_tamper_detected(common::try_run_lua(&beautified, use_debug));
+    }
+}
+
+#[test]
+#[cfg_attr(test_lua = "luau", ignore = "hooks are found by the debug checks, which LuaU output leaves out")]
+fn hooked_functions_are_detected() {
+    let config = common::step_config("AntiTamper", USE_DEBUG, 1);
+    let out = Pipeline::from_config(config).unwrap().apply(programs::ERRORS).unwrap();
+    for hook in ["pcall", "string.sub", "debug.getinfo"] {
+        let tampered = format!(
+            "local original = {hook} {hook} = function(...) return original(...) end {out}"
+        );
+        assert_tamper_detected(common::try_run_lua(&tampered, true));
+    }
+}
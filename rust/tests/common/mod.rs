@@ -1,24 +1,58 @@
-use mlua::{Lua, Value, Variadic};
-use prometheus_rs::{load_preset, Pipeline};
+#![allow(dead_code)]
+
+use mlua::{Lua, LuaOptions, StdLib, Value, Variadic};
+use prometheus_rs::{load_preset, Config, LuaVersion, Pipeline};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+pub mod programs;
+
+/// Lua version of the interpreter that runs the tests, selected with the
+/// `test_lua` cfg. Lua 5.4 runs Lua 5.1 output.
+pub const LUA_VERSION: LuaVersion =
+    if cfg!(test_lua = "luau") { LuaVersion::LuaU } else { LuaVersion::Lua51 };
+
 /// Obfuscate `code` and assert that running the result
 /// produces the same output as the original program.
 pub fn assert_equivalent(code: &str) {
-    let config = load_preset("Minify").expect("preset should exist");
+    assert_equivalent_with(code, load_preset("Minify").expect("preset should exist"));
+}
+
+/// Like [`assert_equivalent`], but obfuscates with `config`.
+pub fn assert_equivalent_with(code: &str, config: Config) {
     let mut pipeline = Pipeline::from_config(config).expect("pipeline should build");
-    let obfuscated = pipeline
-        .apply(code)
-        .unwrap_or_else(|_| code.to_string());
+    let obfuscated = pipeline.apply(code).expect("obfuscation should succeed");
 
     let original = run_lua(code);
     let obf = run_lua(&obfuscated);
     assert_eq!(original, obf, "obfuscated output differed");
 }
 
+/// A config for [`LUA_VERSION`] that applies the single step `name` with
+/// `settings`, a JSON object, seeded with `seed`. Other keys keep their
+/// defaults.
+pub fn step_config(name: &str, settings: &str, seed: u64) -> Config {
+    let mut config = Config::from_json(&format!(
+        r#"{{ "Seed": {seed}, "Steps": [{{ "Name": "{name}", "Settings": {settings} }}] }}"#
+    ))
+    .expect("config should parse");
+    config.lua_version = LUA_VERSION;
+    config
+}
+
 fn run_lua(code: &str) -> String {
-    let lua = Lua::new();
+    try_run_lua(code, true).expect("lua exec failed")
+}
+
+/// Run `code` and return what it printed, or the error it raised. `debug`
+/// controls whether the debug library is available to the script.
+pub fn try_run_lua(code: &str, debug: bool) -> mlua::Result<String> {
+    let lua = if debug {
+        // SAFETY: the debug library is only used by the scripts under test.
+        unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE | StdLib::DEBUG, LuaOptions::new()) }
+    } else {
+        Lua::new()
+    };
     let out = Rc::new(RefCell::new(String::new()));
     {
         let out = out.clone();
@@ -47,6 +81,7 @@ fn run_lua(code: &str) -> String {
             .set("print", print)
             .expect("failed to set print function");
     }
-    lua.load(code).exec().expect("lua exec failed");
-    out.borrow().clone()
+    lua.load(code).exec()?;
+    let out = out.borrow().clone();
+    Ok(out)
 }
@@ -0,0 +1,119 @@
+//! Programs that the steps are checked against, each stressing the parts of
+//! the language that some step rewrites.
+
+/// String and number constants, including bytes that need escaping.
+pub const CONSTANTS: &str = r#"
+local greeting = "Hello"
+local t = {name = "world", [10] = 2.5, "x\0y\255z", "\226\130\172"}
+print(greeting .. ", " .. t.name .. "!", " ")
+print(t[10] * 4, " ", #t[1], " ", t[1]:byte(2), t[1]:byte(4), " ", #t[2])
+local s = ""
+for i = 1, 3 do s = s .. ("ab"):rep(i) .. "|" end
+print(s, 1e3, -7, 0.125, 2^53)
+"#;
+
+/// Nested functions reading constants and upvalues.
+pub const FUNCTIONS: &str = r#"
+local prefix = "id:"
+local function label(n)
+    local function inner(m) return prefix .. tostring(m) .. "/" .. "x" end
+    return inner(n) .. (function() return "!" .. 42 end)()
+end
+for i = 1, 3 do print(label(i * 100), " ") end
+print(("done"):upper(), 65535, -65535)
+"#;
+
+/// Repeated strings, binary table keys and long strings.
+pub const STRINGS: &str = r#"
+local secret = "attack at dawn"
+local t = {key = "value", ["x\0y\255"] = "\226\130\172"}
+for i = 1, 3 do print(secret, t.key, " ") end
+print(#t["x\0y\255"], t["x\0y\255"]:byte(1, -1))
+print(("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"):len(), "", ("z"):rep(300):sub(298))
+"#;
+
+/// Strings long enough to be split, in expressions and nested functions.
+pub const LONG_STRINGS: &str = r#"
+local greeting = "Hello, World! This string is long enough to be split"
+local t = {alpha = "first value", ["a key with spaces"] = "x\0y\255z"}
+print(greeting, #greeting, " ", t.alpha, " ", #t["a key with spaces"])
+local function describe(n)
+    local function inner() return "inner text for " .. n end
+    return "number " .. n .. ": " .. inner()
+end
+for i = 1, 3 do print(describe(i), " ") end
+print(("abcdefghijklmnopqrstuvwxyz"):upper(), #("z"):rep(10), ("q"):rep(400):sub(-3))
+"#;
+
+/// Integers and floats whose type and value must survive being rewritten.
+pub const NUMBERS: &str = r#"
+local values = {0, 1, -1, 7, 255, 65536, 0.5, 0.1, -2.75, 1e-10, 123456.789, 2^31, 4503599627370496, 1e300}
+for i, v in ipairs(values) do
+    print(v, " ", math.type and math.type(v) or type(v), " ")
+end
+print(math.floor(10 / 3), 7 % -3, 2^10, -3 * -4, 1 - -1, #values)
+local t = {}
+for i = 1, 10, 3 do t[#t + 1] = i end
+print(table.concat(t, ","))
+"#;
+
+/// Locals in every position: multiple assignment, recursion, upvalues,
+/// methods and shadowing.
+pub const LOCALS: &str = r##"
+local a, b = 1, 2
+a, b = b, a
+local c, d, e = (function() return 3, 4, 5 end)()
+local none
+print(a, b, c, d, e, none, " ")
+local function fact(n)
+    if n <= 1 then return 1 end
+    return n * fact(n - 1)
+end
+local counter = 0
+local function inc() counter = counter + 1 return counter end
+inc() inc()
+print(fact(6), " ", counter, " ")
+local obj = { n = 1 }
+function obj.double(x) return x * 2 end
+function obj:add(x) self.n = self.n + x return self end
+obj:add(4):add(5)
+obj.n = obj.n + obj.double(1)
+print(obj.n, " ", #obj, " ")
+local s = "hello"
+s = s .. " world"
+print(s:upper(), " ", #s, " ")
+local t = {}
+local i = 0
+repeat
+    local j = i * i
+    t[#t + 1] = j
+    i = i + 1
+until j > 10
+local k, t2 = 1, t
+t2[k], k = "first", 2
+print(table.concat(t, ","), " ", k, " ")
+local x, x = 1, 2
+print(x)
+"##;
+
+/// Methods, varargs and anonymous functions.
+pub const METHODS: &str = r##"
+local function add(a, b) return a + b end
+local t = { n = 0 }
+function t:inc(by) self.n = self.n + (by or 1) return self end
+local function count(...) return select("#", ...) end
+t:inc():inc(4)
+print(add(1, 2), " ", t.n, " ", count(1, nil, 3), " ", (function() return "ok" end)())
+"##;
+
+/// Recursion and errors caught by `pcall`.
+pub const ERRORS: &str = r##"
+local function fib(n)
+    if n < 2 then return n end
+    return fib(n - 1) + fib(n - 2)
+end
+local ok, err = pcall(error, "boom", 0)
+print(fib(15), " ", ok, " ", err, " ", select("#", 1, 2, 3))
+"##;
+
+pub const ALL: [&str; 8] = [CONSTANTS, FUNCTIONS, STRINGS, LONG_STRINGS, NUMBERS, LOCALS, METHODS, ERRORS];
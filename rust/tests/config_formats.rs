@@ -0,0 +1,89 @@
+use prometheus_rs::{load_preset, preset_names, Config, ConfigFormat, Pipeline};
+
+const FORMATS: [ConfigFormat; 3] = [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Lua];
+
+fn obfuscate(config: Config) -> String {
+    Pipeline::from_config(config).unwrap().apply("print('hello', 1 + 2)").unwrap()
+}
+
+#[test]
+fn presets_survive_conversion_between_formats() {
+    for name in preset_names() {
+        let mut preset = load_preset(name).unwrap();
+        preset.seed = 5;
+        let expected = obfuscate(preset.clone());
+        for from in FORMATS {
+            let text = preset.to_text(from).unwrap();
+            let config = Config::parse(&text, from).unwrap_or_else(|err| panic!("{err}\n{text}"));
+            for to in FORMATS {
+                let converted = Config::parse(&config.to_text(to).unwrap(), to).unwrap();
+                assert_eq!(obfuscate(converted), expected, "{name}: {from:?} to {to:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn toml_configs_load() {
+    let config = Config::from_toml(
+        r#"
+LuaVersion = "LuaU"
+NameGenerator = "Il"
+Seed = 9
+
+[[Steps]]
+Name = "ConstantArray"
+Settings = { Treshold = 0.5, Encoding = "none" }
+
+[[Steps]]
+Name = "WrapInFunction"
+"#,
+    )
+    .expect("config should load");
+    assert!(matches!(config.lua_version, prometheus_rs::LuaVersion::LuaU));
+    assert_eq!(config.seed, 9);
+    let names: Vec<&str> = config.steps.iter().map(|step| step.name.as_str()).collect();
+    assert_eq!(names, ["ConstantArray", "WrapInFunction"]);
+    assert_eq!(config.steps[0].settings["Encoding"], "none");
+    Pipeline::from_config(config).expect("settings should be valid");
+}
+
+#[test]
+fn formats_are_selected_by_extension() {
+    let format = |path: &str| ConfigFormat::from_path(path.as_ref());
+    assert_eq!(format("config.json"), Some(ConfigFormat::Json));
+    assert_eq!(format("dir/config.TOML"), Some(ConfigFormat::Toml));
+    assert_eq!(format("config.lua"), Some(ConfigFormat::Lua));
+    assert_eq!(format("config.yaml"), None);
+    assert_eq!(format("config"), None);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let cases = [
+        (r#"{ "Steps": [{ "Name": "WrapInFunction", "Setings": { "Iterations": 3 } }] }"#, "unknown field `Setings`"),
+        (r#"{ "steps": [{ "Name": "WrapInFunction" }] }"#, "unknown field `steps`"),
+    ];
+    for (json, expected) in cases {
+        let err = Config::from_json(json).expect_err("unknown key should be rejected");
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+    let err = Config::from_lua(r#"return { Steps = { { Name = "Vmify", Setings = {} } } }"#)
+        .expect_err("unknown key should be rejected");
+    assert!(err.to_string().contains("unknown field `Setings`"), "{err}");
+    let err = Config::from_toml("seed = 1").expect_err("unknown key should be rejected");
+    assert!(err.to_string().contains("unknown field `seed`"), "{err}");
+}
+
+#[test]
+fn lua_configs_hold_seeds_up_to_2_pow_53() {
+    let mut config = Config { seed: 1 << 53, ..Config::default() };
+    let text = config.to_text(ConfigFormat::Lua).unwrap();
+    assert_eq!(Config::from_lua(&text).unwrap().seed, 1 << 53, "{text}");
+
+    config.seed = (1 << 53) + 1;
+    let err = config.to_text(ConfigFormat::Lua).expect_err("seed should not fit");
+    assert!(err.to_string().contains("9007199254740993 cannot be written"), "{err}");
+    let json = config.to_text(ConfigFormat::Json).unwrap();
+    assert_eq!(Config::from_json(&json).unwrap().seed, (1 << 53) + 1);
+}
@@ -0,0 +1,28 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn constants_are_hidden() {
+    let mut pipeline = Pipeline::from_config(common::step_config("ConstantArray", "{}", 7)).unwrap();
+    let out = pipeline.apply(programs::CONSTANTS).unwrap();
+    assert!(!out.contains("Hello"), "{out}");
+    assert!(!out.contains("world"), "{out}");
+
+    let config = common::step_config("ConstantArray", r#"{ "Encoding": "none" }"#, 7);
+    let out = Pipeline::from_config(config).unwrap().apply(programs::CONSTANTS).unwrap();
+    assert!(out.contains("\"Hello\""), "{out}");
+    assert!(!out.contains("greeting .. \"Hello\""), "{out}");
+}
+
+#[test]
+fn local_wrappers_hide_positions() {
+    let settings = r#"{ "LocalWrapperCount": 2, "Shuffle": false, "Rotate": false, "Encoding": "none" }"#;
+    let mut pipeline = Pipeline::from_config(common::step_config("ConstantArray", settings, 7)).unwrap();
+    let out = pipeline.apply(programs::FUNCTIONS).unwrap();
+    // Without wrappers the first constant would be read as `array[1]`
+    assert!(!out.contains("[1]"), "{out}");
+    assert!(out.contains("\"id:\""), "{out}");
+}
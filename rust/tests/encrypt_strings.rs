@@ -0,0 +1,24 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn strings_are_hidden() {
+    let mut pipeline = Pipeline::from_config(common::step_config("EncryptStrings", "{}", 3)).unwrap();
+    let out = pipeline.apply(programs::STRINGS).unwrap();
+    for plain in ["attack", "dawn", "value", "aaaa"] {
+        assert!(!out.contains(plain), "{plain} leaked: {out}");
+    }
+}
+
+#[test]
+fn keys_depend_on_seed() {
+    let apply = |seed| {
+        let config = common::step_config("EncryptStrings", "{}", seed);
+        Pipeline::from_config(config).unwrap().apply(programs::STRINGS).unwrap()
+    };
+    assert_eq!(apply(1), apply(1));
+    assert_ne!(apply(1), apply(2));
+}
@@ -0,0 +1,72 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use prometheus_rs::{load_preset, Config, Pipeline};
+
+/// The Strong preset as written in the presets file of the Lua implementation.
+const STRONG: &str = r#"
+return {
+    -- The default LuaVersion is Lua51
+    LuaVersion = "Lua51";
+    -- For minifying no VarNamePrefix is applied
+    VarNamePrefix = "";
+    -- Name Generator for Variables that look like this: IlI1lI1l
+    NameGenerator = "MangledShuffled";
+    -- No pretty printing
+    PrettyPrint = false;
+    -- Seed is generated based on current time
+    Seed = 0;
+    -- Obfuscation steps
+    Steps = {
+        { Name = "Vmify"; Settings = {}; },
+        { Name = "EncryptStrings"; Settings = {}; },
+        { Name = "AntiTamper"; Settings = {}; },
+        { Name = "Vmify"; Settings = {}; },
+        {
+            Name = "ConstantArray";
+            Settings = {
+                Treshold = 1;
+                StringsOnly = true;
+                Shuffle = true;
+                Rotate = true;
+                LocalWrapperTreshold = 0;
+            }
+        },
+        { Name = "NumbersToExpressions"; Settings = {}; },
+        { Name = "WrapInFunction"; Settings = {}; },
+    }
+}
+"#;
+
+fn obfuscate(config: Config) -> String {
+    Pipeline::from_config(config).unwrap().apply("print('hello', 1 + 2)").unwrap()
+}
+
+#[test]
+fn upstream_config_matches_preset() {
+    let config = Config::from_lua(STRONG).expect("config should load");
+    assert_eq!(obfuscate(config), obfuscate(load_preset("Strong").unwrap()));
+}
+
+#[test]
+fn missing_keys_take_the_defaults() {
+    let config = Config::from_lua(r#"return { Steps = { { Name = "Vmify" } } }"#).expect("config should load");
+    assert_eq!(config.name_generator, "MangledShuffled");
+    assert!(matches!(config.lua_version, prometheus_rs::LuaVersion::Lua51));
+    let out = obfuscate(config);
+    assert_eq!(common::try_run_lua(&out, false).unwrap(), "hello3");
+}
+
+#[test]
+fn empty_steps_load() {
+    let config = Config::from_lua("return { Steps = {} }").expect("config should load");
+    assert!(config.steps.is_empty());
+}
+
+#[test]
+fn settings_are_still_validated() {
+    let config = Config::from_lua(r#"return { Steps = { { Name = "AntiTamper", Settings = { UseDebug = "no" } } } }"#)
+        .expect("config should load");
+    let err = Pipeline::from_config(config).err().expect("setting should be rejected");
+    assert!(err.to_string().contains("expected a boolean, found a string"), "{err}");
+}
@@ -0,0 +1,21 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn numbers_are_replaced() {
+    let config = common::step_config("NumbersToExpressions", r#"{ "Treshold": 1 }"#, 1);
+    let out = Pipeline::from_config(config).unwrap().apply("print(1234567)").unwrap();
+    assert!(!out.contains("1234567"), "{out}");
+}
+
+#[test]
+fn output_is_reproducible() {
+    let apply = |seed| {
+        let config = common::step_config("NumbersToExpressions", "{}", seed);
+        Pipeline::from_config(config).unwrap().apply(programs::NUMBERS).unwrap()
+    };
+    assert_eq!(apply(3), apply(3));
+}
@@ -0,0 +1,51 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use prometheus_rs::{load_preset, preset_names};
+
+const PROGRAM: &str = r#"
+local function greet(name)
+    return "Hello, " .. name .. "!"
+end
+local squares = {}
+for i = 1, 5 do
+    squares[#squares + 1] = i * i
+end
+print(greet("World"), " ", table.concat(squares, ","), " ", 2.5 * 4)
+"#;
+
+#[test]
+fn every_listed_preset_loads() {
+    assert_eq!(preset_names(), ["Minify", "Weak", "Medium", "Strong"]);
+    for name in preset_names() {
+        assert!(load_preset(name).is_some(), "{name}");
+    }
+    assert!(load_preset("Nope").is_none());
+}
+
+#[test]
+fn presets_keep_behaviour() {
+    for name in preset_names() {
+        let mut config = load_preset(name).unwrap();
+        config.lua_version = common::LUA_VERSION;
+        common::assert_equivalent_with(PROGRAM, config);
+    }
+}
+
+#[test]
+fn strong_preset_matches_upstream_steps() {
+    let steps: Vec<String> =
+        load_preset("Strong").unwrap().steps.into_iter().map(|step| step.name).collect();
+    assert_eq!(
+        steps,
+        [
+            "Vmify",
+            "EncryptStrings",
+            "AntiTamper",
+            "Vmify",
+            "ConstantArray",
+            "NumbersToExpressions",
+            "WrapInFunction"
+        ]
+    );
+}
@@ -0,0 +1,20 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn locals_become_proxies() {
+    let config = common::step_config("ProxifyLocals", r#"{ "LiteralType": "number" }"#, 1);
+    let out = Pipeline::from_config(config).unwrap().apply(r#"local message = "hi" print(message)"#).unwrap();
+    assert!(out.contains("setmetatable"), "{out}");
+    assert!(!out.contains("print(message)"), "{out}");
+}
+
+#[test]
+fn works_with_vmify() {
+    let mut config = common::step_config("ProxifyLocals", r#"{ "LiteralType": "string" }"#, 2);
+    config.steps.extend(common::step_config("Vmify", "{}", 2).steps);
+    common::assert_equivalent_with(programs::LOCALS, config);
+}
@@ -0,0 +1,21 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+#[test]
+fn long_strings_stay_within_parser_limits() {
+    let long = format!("print(\"{}\")", "0123456789".repeat(100));
+    let settings = r#"{ "ConcatenationType": "strcat", "MinLength": 1, "MaxLength": 1 }"#;
+    common::assert_equivalent_with(&long, common::step_config("SplitStrings", settings, 11));
+}
+
+#[test]
+fn strings_are_split() {
+    let settings = r#"{ "ConcatenationType": "strcat", "MinLength": 2, "MaxLength": 3 }"#;
+    let mut pipeline = Pipeline::from_config(common::step_config("SplitStrings", settings, 11)).unwrap();
+    let out = pipeline.apply(programs::LONG_STRINGS).unwrap();
+    assert!(!out.contains("Hello"), "{out}");
+    assert!(out.contains(".."), "{out}");
+}
@@ -0,0 +1,83 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::programs;
+use prometheus_rs::Pipeline;
+
+/// Steps that are also checked on their own output. Applying the others
+/// twice is not useful, NumbersToExpressions for one grows exponentially.
+const REAPPLIED: [&str; 4] = ["ConstantArray", "EncryptStrings", "SplitStrings", "ProxifyLocals"];
+
+/// Settings of each step to check, covering every choice of the enums and
+/// the edges of the ranges.
+fn cases() -> Vec<(&'static str, String)> {
+    let mut cases = vec![("ConstantArray", r#"{ "Treshold": 0.5 }"#.to_string())];
+    for encoding in ["none", "base64"] {
+        for shuffle in [false, true] {
+            for rotate in [false, true] {
+                for strings_only in [false, true] {
+                    let settings = format!(
+                        r#"{{ "Encoding": "{encoding}", "Shuffle": {shuffle}, "Rotate": {rotate}, "StringsOnly": {strings_only} }}"#
+                    );
+                    cases.push(("ConstantArray", settings));
+                }
+            }
+        }
+    }
+    for (treshold, count, args, offset) in [(1.0, 1, 1, 0), (1.0, 3, 10, 65535), (0.5, 2, 4, 10), (1.0, 40, 30, 3)] {
+        let settings = format!(
+            r#"{{ "LocalWrapperTreshold": {treshold}, "LocalWrapperCount": {count}, "LocalWrapperArgCount": {args}, "MaxWrapperOffset": {offset} }}"#
+        );
+        cases.push(("ConstantArray", settings));
+    }
+    for cipher in ["lcg", "rc4"] {
+        for use_cache in [true, false] {
+            cases.push(("EncryptStrings", format!(r#"{{ "Cipher": "{cipher}", "UseCache": {use_cache} }}"#)));
+        }
+    }
+    let concatenations =
+        [("strcat", "global"), ("table", "global"), ("custom", "global"), ("custom", "local"), ("custom", "inline")];
+    for (concatenation, function) in concatenations {
+        for (min, max) in [(1, 1), (2, 6), (5, 5)] {
+            let settings = format!(
+                r#"{{ "ConcatenationType": "{concatenation}", "CustomFunctionType": "{function}", "MinLength": {min}, "MaxLength": {max}, "CustomLocalFunctionsCount": 3 }}"#
+            );
+            cases.push(("SplitStrings", settings));
+        }
+    }
+    for internal in [0.0, 0.2, 0.8] {
+        cases.push(("NumbersToExpressions", format!(r#"{{ "Treshold": 1, "InternalTreshold": {internal} }}"#)));
+    }
+    for literal_type in ["dictionary", "number", "string", "any"] {
+        cases.push(("ProxifyLocals", format!(r#"{{ "LiteralType": "{literal_type}" }}"#)));
+    }
+    cases.push(("AddVararg", "{}".to_string()));
+    for use_debug in [false, true] {
+        cases.push(("AntiTamper", format!(r#"{{ "UseDebug": {use_debug} }}"#)));
+    }
+    cases
+}
+
+/// Every case keeps the output of every program, pretty printed with seed 1.
+#[test]
+fn steps_keep_behaviour() {
+    let expected: Vec<String> =
+        programs::ALL.iter().map(|program| common::try_run_lua(program, true).unwrap()).collect();
+    for (step, settings) in cases() {
+        for seed in 0..3 {
+            let reapply = seed == 0 && REAPPLIED.contains(&step);
+            for times in if reapply { 1..=2 } else { 1..=1 } {
+                let mut config = common::step_config(step, &settings, seed);
+                config.pretty_print = seed == 1;
+                config.steps = vec![config.steps[0].clone(); times];
+                let context = format!("{step} {settings} x{times}, seed {seed}");
+                let mut pipeline = Pipeline::from_config(config).unwrap_or_else(|err| panic!("{context}: {err}"));
+                for (program, expected) in programs::ALL.iter().zip(&expected) {
+                    let out = pipeline.apply(program).unwrap_or_else(|err| panic!("{context}: {err}"));
+                    let actual = common::try_run_lua(&out, true).unwrap_or_else(|err| panic!("{context}: {err}\n{out}"));
+                    assert_eq!(&actual, expected, "{context}\n{out}");
+                }
+            }
+        }
+    }
+}
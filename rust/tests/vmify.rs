@@ -0,0 +1,135 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use prometheus_rs::Config;
+
+fn assert_vmified(code: &str) {
+    let config = Config::from_json(r#"{ "LuaVersion": "Lua51", "NameGenerator": "MangledShuffled", "Steps": [{ "Name": "Vmify" }] }"#)
+        .expect("config should parse");
+    common::assert_equivalent_with(code, config);
+}
+
+#[test]
+fn closures_capture_per_iteration() {
+    assert_vmified(
+        r##"
+local fns = {}
+for i = 1, 5 do
+    local x = i * 2
+    fns[i] = function() x = x + 1 return x end
+end
+for _, f in ipairs(fns) do print(f(), f(), " ") end
+
+local function counter()
+    local n = 0
+    return function()
+        return function() n = n + 1 return n end
+    end
+end
+local inc = counter()()
+inc() inc()
+print(inc())
+"##,
+    );
+}
+
+#[test]
+fn varargs_and_multiple_returns() {
+    assert_vmified(
+        r##"
+local function pass(...) return ... end
+local function count(...) return select("#", ...) end
+local function pair() return 1, 2 end
+print(count(pass(1, nil, 3, nil)), " ")
+print(count(pair(), pair()), " ")
+local t = {pair(), pair()}
+print(#t, " ", t[1], t[2], t[3])
+local a, b, c = pair()
+print(a, b, c)
+print(select(2, "a", "b", "c"))
+"##,
+    );
+}
+
+#[test]
+fn control_flow() {
+    assert_vmified(
+        r##"
+local sum = 0
+for i = 10, 1, -2 do sum = sum + i end
+print(sum, " ")
+for i = 1, 100 do
+    if i > 3 then break end
+    print(i)
+end
+local i = 0
+repeat
+    local j = i
+    i = i + 1
+until j >= 4
+print(" ", i)
+while true do
+    i = i - 1
+    if i == 2 then break elseif i == 3 then print("three") else print("other") end
+end
+for k, v in pairs({x = 1}) do print(k, v) end
+print(nil or "default", false and 1, 1 and 2, 1 < 2, "b" >= "a", not nil)
+"##,
+    );
+}
+
+#[test]
+fn tables_methods_and_recursion() {
+    assert_vmified(
+        r##"
+local Account = {}
+Account.__index = Account
+function Account.new(balance) return setmetatable({balance = balance}, Account) end
+function Account:deposit(v) self.balance = self.balance + v return self end
+local acc = Account.new(10):deposit(5)
+print(acc.balance, " ")
+print(("abc"):upper(), #"hello", " ")
+local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+print(fib(15), " ")
+local big = {}
+for i = 1, 120 do big[i] = i end
+local copy = {table.unpack and table.unpack(big) or unpack(big)}
+print(#copy, " ", copy[120])
+counter = 0
+local function bump() counter = counter + 1 end
+bump() bump()
+print(counter, 2 ^ 10, 7 % 3, -(3), "a" .. 1 .. "b")
+"##,
+    );
+}
+
+#[test]
+fn interpreter_can_be_vmified_again() {
+    let config = Config::from_json(
+        r#"{ "LuaVersion": "Lua51", "NameGenerator": "MangledShuffled", "Steps": [{ "Name": "Vmify" }, { "Name": "Vmify" }] }"#,
+    )
+    .expect("config should parse");
+    common::assert_equivalent_with(
+        r##"
+local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+print(fib(10), select("#", 1, nil, 3), type(print))
+"##,
+        config,
+    );
+}
+
+#[test]
+#[cfg_attr(test_lua = "luau", ignore = "LuaU does not eliminate tail calls")]
+fn tail_calls_do_not_grow_the_stack() {
+    assert_vmified(
+        r##"
+local function count(n, acc)
+    if n == 0 then return acc end
+    return count(n - 1, acc + 1)
+end
+local object = {}
+function object:down(n) if n == 0 then return "done" end return self:down(n - 1) end
+print(count(200000, 0), " ", object:down(200000), " ", select("#", (function(...) return select(1, ...) end)(1, 2, 3)))
+"##,
+    );
+}
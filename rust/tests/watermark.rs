@@ -0,0 +1,47 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use prometheus_rs::{Config, Pipeline, PrometheusError};
+
+const CONTENT: &str = "Licensed to ACME Corp";
+
+fn config(name: &str, settings: &str) -> Config {
+    common::step_config(name, settings, 1)
+}
+
+fn obfuscate(code: &str, config: Config) -> String {
+    Pipeline::from_config(config).unwrap().apply(code).unwrap()
+}
+
+#[test]
+fn watermark_is_stored_in_the_custom_variable() {
+    let settings = format!(r#"{{ "Content": "{CONTENT}", "CustomVariable": "_LICENSE" }}"#);
+    let out = obfuscate("print(_LICENSE)", config("Watermark", &settings));
+    assert_eq!(common::try_run_lua(&out, false).unwrap(), CONTENT);
+}
+
+#[test]
+fn invalid_custom_variable_is_rejected() {
+    let config = config("Watermark", r#"{ "CustomVariable": "not a name" }"#);
+    let err = Pipeline::from_config(config).unwrap().apply("print(1)").unwrap_err();
+    assert!(matches!(err, PrometheusError::Step { .. }), "{err}");
+}
+
+#[test]
+fn checked_script_keeps_behaviour_and_readable_watermark() {
+    let mut config = config("WatermarkCheck", &format!(r#"{{ "Content": "{CONTENT}" }}"#));
+    config.steps.extend(common::step_config("EncryptStrings", "{}", 1).steps);
+    let code = "local t = {} for i = 1, 3 do t[i] = i end print(table.concat(t, '-'))";
+    common::assert_equivalent_with(code, config.clone());
+    let out = obfuscate(code, config);
+    assert_eq!(out.matches(CONTENT).count(), 1, "{out}");
+}
+
+#[test]
+fn tampered_watermark_halts_the_script() {
+    let settings = format!(r#"{{ "Content": "{CONTENT}" }}"#);
+    let out = obfuscate("print('running')", config("WatermarkCheck", &settings));
+    assert_eq!(common::try_run_lua(&out, false).unwrap(), "running");
+    let tampered = out.replacen(CONTENT, "Licensed to nobody", 1);
+    assert_eq!(common::try_run_lua(&tampered, false).unwrap(), "");
+}
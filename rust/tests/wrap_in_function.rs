@@ -0,0 +1,88 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use mlua::{Lua, MultiValue, Table};
+use prometheus_rs::{Config, Pipeline};
+
+const MODULE: &str = r##"
+local name, count = ...
+local M = { name = name, count = select("#", ...) }
+function M.greet(who)
+    return "hello " .. who
+end
+return M, "second"
+"##;
+
+fn config(iterations: u64) -> Config {
+    common::step_config("WrapInFunction", &format!(r#"{{ "Iterations": {iterations} }}"#), 1)
+}
+
+fn obfuscate(code: &str, iterations: u64) -> String {
+    let mut pipeline = Pipeline::from_config(config(iterations)).unwrap();
+    pipeline.apply(code).unwrap()
+}
+
+#[test]
+fn behaviour_is_kept() {
+    for iterations in [1, 3] {
+        common::assert_equivalent_with(
+            "local t = {} for i = 1, 5 do t[i] = i * i end print(table.concat(t, ','))",
+            config(iterations),
+        );
+    }
+}
+
+#[test]
+fn chunk_is_wrapped_once_per_iteration() {
+    for iterations in [1, 3] {
+        let out = obfuscate("print(1)", iterations);
+        assert_eq!(out.matches("function").count(), iterations as usize, "{out}");
+    }
+}
+
+#[test]
+fn varargs_and_return_values_are_forwarded() {
+    for iterations in [1, 3] {
+        let out = obfuscate(MODULE, iterations);
+        let lua = Lua::new();
+        let results: MultiValue = lua
+            .load(&out)
+            .call(("module", 42, mlua::Value::Nil))
+            .expect("wrapped module should run");
+        let mut results = results.into_iter();
+        let module = match results.next() {
+            Some(mlua::Value::Table(module)) => module,
+            other => panic!("expected the module table, got {other:?}"),
+        };
+        assert_eq!(module.get::<_, String>("name").unwrap(), "module");
+        assert_eq!(module.get::<_, i64>("count").unwrap(), 3);
+        let greet: mlua::Function = module.get("greet").unwrap();
+        assert_eq!(greet.call::<_, String>("world").unwrap(), "hello world");
+        assert!(matches!(results.next(), Some(mlua::Value::String(s)) if s == "second"));
+    }
+}
+
+#[test]
+#[cfg_attr(test_lua = "luau", ignore = "LuaU has no package library")]
+fn wrapped_module_can_be_required() {
+    let out = obfuscate(MODULE, 2);
+    let lua = Lua::new();
+    let preload: Table = lua.globals().get::<_, Table>("package").unwrap().get("preload").unwrap();
+    preload.set("wrapped", lua.load(&out).into_function().unwrap()).unwrap();
+    let module: Table = lua.load(r#"return require("wrapped")"#).eval().unwrap();
+    assert_eq!(module.get::<_, String>("name").unwrap(), "wrapped");
+}
+
+#[test]
+fn iterations_must_be_whole_numbers() {
+    let config = |iterations: &str| {
+        Config::from_json(&format!(
+            r#"{{ "Steps": [{{ "Name": "WrapInFunction", "Settings": {{ "Iterations": {iterations} }} }}] }}"#
+        ))
+        .expect("config should parse")
+    };
+    let out = Pipeline::from_config(config("2.0")).unwrap().apply("print(1)").unwrap();
+    assert_eq!(out.matches("function").count(), 2, "{out}");
+    let err = Pipeline::from_config(config("2.5")).err().expect("2.5 should be rejected");
+    assert!(err.to_string().contains("expected a whole number, found 2.5"), "{err}");
+}